@@ -0,0 +1,124 @@
+
+//! A small read-only HTTP API, so that local displays can poll the Pi for
+//! readings directly rather than going through the broker.
+//!
+//! - `GET /sensors` lists the discovered sensors
+//! - `GET /sensors/<id>` returns the latest reading of one sensor, by its id
+//!   or alias
+//! - `GET /health` reports whether readings are flowing and the broker is
+//!   connected
+
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use serde_json::json;
+
+use crate::config::SensorsConfig;
+use crate::http;
+use crate::payload::Payload;
+use crate::sensors::{SensorInfo, SensorList};
+
+
+#[derive(Default)]
+struct State
+{
+    sensors: Vec<SensorInfo>,
+    latest: Option<Payload>,
+    /// None if there is no broker to be connected to.
+    broker_connected: Option<bool>
+}
+
+
+/// What the API serves, shared between the read loop and the server.
+#[derive(Clone)]
+pub struct Api
+{
+    host: String,
+    state: Arc<Mutex<State>>
+}
+
+impl Api
+{
+    pub fn new(host: &str) -> Api
+    {
+        Api { host: host.into(), state: Arc::default() }
+    }
+
+    pub fn set_sensors(&self, sensors: &SensorList, config: &SensorsConfig)
+    {
+        self.state.lock().unwrap().sensors = sensors.iter()
+            .map(|sensor| SensorInfo::new(sensor.as_ref(), config.metadata(sensor.identifier())))
+            .collect();
+    }
+
+    /// Keep the readings from the latest cycle.
+    pub fn record(&self, payload: &Payload)
+    {
+        self.state.lock().unwrap().latest = Some(payload.clone());
+    }
+
+    pub fn set_broker_connected(&self, connected: bool)
+    {
+        self.state.lock().unwrap().broker_connected = Some(connected);
+    }
+
+    /// The status code and JSON body answering `method` on `path`.
+    pub fn respond(&self, method: &str, path: &str) -> (u16, String)
+    {
+        if method != "GET" {
+            return (405, json!({ "error": "only GET is supported" }).to_string());
+        }
+
+        let state = self.state.lock().unwrap();
+
+        match path.trim_end_matches('/') {
+            "/sensors" => (200, json!({ "host": self.host, "sensors": state.sensors }).to_string()),
+            "/health" => {
+                let broker = match state.broker_connected {
+                    Some(true) => "connected",
+                    Some(false) => "disconnected",
+                    None => "not configured"
+                };
+                let healthy = state.latest.is_some() && state.broker_connected != Some(false);
+                let body = json!({
+                    "status": if healthy { "ok" } else { "degraded" },
+                    "broker": broker,
+                    "last_read": state.latest.as_ref().map(|p| &p.time)
+                });
+                (if healthy { 200 } else { 503 }, body.to_string())
+            },
+            other => {
+                let id = match other.strip_prefix("/sensors/") {
+                    Some(id) => id,
+                    None => return (404, json!({ "error": "not found" }).to_string())
+                };
+                let sensor = match state.sensors.iter().find(|s| s.id == id || s.key() == id) {
+                    Some(sensor) => sensor,
+                    None => return (404, json!({ "error": format!("unknown sensor '{}'", id) }).to_string())
+                };
+
+                let entry = state.latest.as_ref().and_then(|p| p.sensors.get(sensor.key()).map(|e| (p, e)));
+                match entry {
+                    Some((payload, entry)) => (200, json!(payload.sensor_message(entry)).to_string()),
+                    None => (503, json!({ "error": "no reading yet" }).to_string())
+                }
+            }
+        }
+    }
+}
+
+
+/// Serve the API on `listen` from a background thread, for as long as the
+/// process runs.
+pub fn serve(listen: SocketAddr, api: Api) -> Result<(), Box<dyn Error>>
+{
+    http::serve(listen, move |method, path| {
+        let (status, body) = api.respond(method, path);
+        tiny_http::Response::from_string(body)
+            .with_status_code(status)
+            .with_header(http::header("Content-Type", "application/json"))
+            // Displays are usually served from somewhere else
+            .with_header(http::header("Access-Control-Allow-Origin", "*"))
+    })
+}
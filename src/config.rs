@@ -0,0 +1,1051 @@
+
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::discovery;
+use crate::filelog::LogFormat;
+use crate::layout::TopicLayout;
+use crate::mqtt::{ProtocolVersion, Transport};
+use crate::payload::TimeZone;
+use crate::sensors::{am2320, ds18b20, RetryPolicy, SensorMetadata};
+
+
+/// Where the configuration file is looked for if no path is given.
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/sensor_reader.toml";
+
+const DEFAULT_RESCAN_INTERVAL: Duration = Duration::from_secs(60);
+const DEFAULT_QUEUE_MAX_BYTES: u64 = 10 * 1024 * 1024;
+const DEFAULT_QUEUE_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+const DEFAULT_LOG_MAX_FILE_BYTES: u64 = 10 * 1024 * 1024;
+const DEFAULT_LOG_MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// The longest duration any setting takes, about ten years. Anything longer
+/// is a mistake, and could overflow once added to the current time.
+const MAX_SECONDS: f32 = 10.0 * 365.0 * 24.0 * 60.0 * 60.0;
+
+/// Topic levels under the base topic that are taken by the node itself, so
+/// a sensor can't be given them as an alias.
+const RESERVED_ALIASES: &[&str] = &["status", "events", "metadata"];
+
+/// How the ids of discovered sensors start. An alias can't, or it could
+/// collide with a sensor that isn't configured.
+const SENSOR_ID_PREFIXES: &[&str] = &[ds18b20::ID_PREFIX, am2320::ID_PREFIX];
+
+
+#[derive(Debug)]
+pub enum ConfigError
+{
+    /// The configuration file could not be read.
+    Io(PathBuf, io::Error),
+    /// The configuration file is not valid TOML, or has the wrong shape.
+    Parse(PathBuf, toml::de::Error),
+    /// The configuration was read, but some settings are missing or invalid.
+    Invalid(Vec<String>)
+}
+
+impl fmt::Display for ConfigError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "could not read {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "could not parse {}: {}", path.display(), e),
+            ConfigError::Invalid(problems) => {
+                write!(f, "invalid configuration:")?;
+                for problem in problems {
+                    write!(f, "\n  - {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for ConfigError
+{
+    fn source(&self) -> Option<&(dyn Error + 'static)>
+    {
+        match self {
+            ConfigError::Io(_, e) => Some(e),
+            ConfigError::Parse(_, e) => Some(e),
+            ConfigError::Invalid(_) => None
+        }
+    }
+}
+
+
+// The file layout. Everything is optional here, because any of it can also
+// come from the environment; whether the result is complete is checked when
+// it is resolved into a Config.
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct RawConfig
+{
+    host: Option<String>,
+    read: RawReadConfig,
+    timestamps: RawTimestampConfig,
+    sensors: RawSensorsConfig,
+    sinks: RawSinksConfig,
+    metrics: RawMetricsConfig,
+    history: RawHistoryConfig,
+    api: RawApiConfig
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct RawReadConfig
+{
+    interval: Option<f32>,
+    retries: Option<u32>,
+    retry_backoff: Option<f32>,
+    deadline: Option<f32>,
+    rescan_interval: Option<f32>
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct RawTimestampConfig
+{
+    timezone: Option<String>,
+    per_sensor: Option<bool>
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct RawSensorsConfig
+{
+    w1_device_path: Option<PathBuf>,
+    am2320_device: Option<PathBuf>,
+    devices: BTreeMap<String, RawDeviceConfig>
+}
+
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+struct RawDeviceConfig
+{
+    retries: Option<u32>,
+    retry_backoff: Option<f32>,
+    alias: Option<String>,
+    location: Option<String>,
+    description: Option<String>,
+    tags: Option<Vec<String>>
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct RawFileLogConfig
+{
+    dir: Option<PathBuf>,
+    format: Option<LogFormat>,
+    max_file_bytes: Option<u64>,
+    rotate_daily: Option<bool>,
+    compress: Option<bool>,
+    max_age: Option<f32>
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct RawMetricsConfig
+{
+    listen: Option<SocketAddr>
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct RawApiConfig
+{
+    listen: Option<SocketAddr>
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct RawHistoryConfig
+{
+    path: Option<PathBuf>
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct RawSinksConfig
+{
+    mqtt: RawMqttConfig,
+    influxdb: RawInfluxConfig,
+    file: RawFileLogConfig
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct RawMqttConfig
+{
+    transport: Option<Transport>,
+    version: Option<ProtocolVersion>,
+    host: Option<String>,
+    port: Option<i32>,
+    ws_path: Option<String>,
+    user: Option<String>,
+    password: Option<String>,
+    password_file: Option<PathBuf>,
+    topic: Option<String>,
+    qos: Option<i32>,
+    ca_cert: Option<PathBuf>,
+    client_cert: Option<PathBuf>,
+    client_cert_key: Option<PathBuf>,
+    client_cert_key_pass: Option<String>,
+    client_cert_key_pass_file: Option<PathBuf>,
+    topic_layout: Option<TopicLayout>,
+    discovery: Option<bool>,
+    discovery_prefix: Option<String>,
+    queue_dir: Option<PathBuf>,
+    queue_max_bytes: Option<u64>,
+    queue_max_age: Option<f32>,
+    message_expiry: Option<f32>
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct RawInfluxConfig
+{
+    url: Option<String>,
+    database: Option<String>,
+    user: Option<String>,
+    password: Option<String>,
+    password_file: Option<PathBuf>,
+    org: Option<String>,
+    bucket: Option<String>,
+    token: Option<String>,
+    token_file: Option<PathBuf>,
+    measurement: Option<String>,
+    batch_size: Option<usize>,
+    flush_interval: Option<f32>,
+    retries: Option<u32>,
+    retry_backoff: Option<f32>
+}
+
+
+/// Overwrites settings with values from the environment, collecting any
+/// values that don't parse rather than stopping at the first.
+struct EnvOverrides<'a>
+{
+    lookup: &'a dyn Fn(&str) -> Option<String>,
+    problems: Vec<String>
+}
+
+impl<'a> EnvOverrides<'a>
+{
+    fn set<T: FromStr>(&mut self, name: &str, target: &mut Option<T>)
+    {
+        if let Some(value) = (self.lookup)(name) {
+            match T::from_str(&value) {
+                Ok(v) => *target = Some(v),
+                Err(_) => self.problems.push(format!("{}: cannot parse '{}'", name, value))
+            }
+        }
+    }
+}
+
+impl RawConfig
+{
+    fn apply_env(&mut self, env: &mut EnvOverrides)
+    {
+        env.set("HOSTNAME", &mut self.host);
+
+        env.set("MQTT_READ_INTERVAL", &mut self.read.interval);
+        env.set("READ_RETRIES", &mut self.read.retries);
+        env.set("READ_RETRY_BACKOFF", &mut self.read.retry_backoff);
+        env.set("READ_DEADLINE", &mut self.read.deadline);
+        env.set("RESCAN_INTERVAL", &mut self.read.rescan_interval);
+
+        env.set("TIMESTAMP_TIMEZONE", &mut self.timestamps.timezone);
+        env.set("SENSOR_TIMESTAMPS", &mut self.timestamps.per_sensor);
+
+        env.set("W1_DEVICE_PATH", &mut self.sensors.w1_device_path);
+        env.set("AM2320_I2C_DEVICE", &mut self.sensors.am2320_device);
+
+        env.set("METRICS_LISTEN", &mut self.metrics.listen);
+        env.set("HISTORY_DB", &mut self.history.path);
+        env.set("API_LISTEN", &mut self.api.listen);
+
+        let mqtt = &mut self.sinks.mqtt;
+        env.set("MQTT_TRANSPORT", &mut mqtt.transport);
+        env.set("MQTT_VERSION", &mut mqtt.version);
+        env.set("MQTT_HOST", &mut mqtt.host);
+        env.set("MQTT_PORT", &mut mqtt.port);
+        env.set("MQTT_WS_PATH", &mut mqtt.ws_path);
+        env.set("MQTT_USER", &mut mqtt.user);
+        env.set("MQTT_PASSWORD", &mut mqtt.password);
+        env.set("MQTT_PASSWORD_FILE", &mut mqtt.password_file);
+        env.set("MQTT_TOPIC", &mut mqtt.topic);
+        env.set("MQTT_QOS", &mut mqtt.qos);
+        env.set("MQTT_TOPIC_LAYOUT", &mut mqtt.topic_layout);
+        env.set("CA_CERT", &mut mqtt.ca_cert);
+        env.set("CLIENT_CERT", &mut mqtt.client_cert);
+        env.set("CLIENT_CERT_KEY", &mut mqtt.client_cert_key);
+        env.set("CLIENT_CERT_KEY_PASS", &mut mqtt.client_cert_key_pass);
+        env.set("CLIENT_CERT_KEY_PASS_FILE", &mut mqtt.client_cert_key_pass_file);
+        env.set("HA_DISCOVERY", &mut mqtt.discovery);
+        env.set("HA_DISCOVERY_PREFIX", &mut mqtt.discovery_prefix);
+        env.set("MQTT_QUEUE_DIR", &mut mqtt.queue_dir);
+        env.set("MQTT_QUEUE_MAX_BYTES", &mut mqtt.queue_max_bytes);
+        env.set("MQTT_QUEUE_MAX_AGE", &mut mqtt.queue_max_age);
+        env.set("MQTT_MESSAGE_EXPIRY", &mut mqtt.message_expiry);
+
+        let influxdb = &mut self.sinks.influxdb;
+        env.set("INFLUXDB_URL", &mut influxdb.url);
+        env.set("INFLUXDB_DATABASE", &mut influxdb.database);
+        env.set("INFLUXDB_USER", &mut influxdb.user);
+        env.set("INFLUXDB_PASSWORD", &mut influxdb.password);
+        env.set("INFLUXDB_PASSWORD_FILE", &mut influxdb.password_file);
+        env.set("INFLUXDB_ORG", &mut influxdb.org);
+        env.set("INFLUXDB_BUCKET", &mut influxdb.bucket);
+        env.set("INFLUXDB_TOKEN", &mut influxdb.token);
+        env.set("INFLUXDB_TOKEN_FILE", &mut influxdb.token_file);
+        env.set("INFLUXDB_MEASUREMENT", &mut influxdb.measurement);
+        env.set("INFLUXDB_BATCH_SIZE", &mut influxdb.batch_size);
+        env.set("INFLUXDB_FLUSH_INTERVAL", &mut influxdb.flush_interval);
+        env.set("INFLUXDB_RETRIES", &mut influxdb.retries);
+        env.set("INFLUXDB_RETRY_BACKOFF", &mut influxdb.retry_backoff);
+
+        let file = &mut self.sinks.file;
+        env.set("FILE_LOG_DIR", &mut file.dir);
+        env.set("FILE_LOG_FORMAT", &mut file.format);
+        env.set("FILE_LOG_MAX_FILE_BYTES", &mut file.max_file_bytes);
+        env.set("FILE_LOG_ROTATE_DAILY", &mut file.rotate_daily);
+        env.set("FILE_LOG_COMPRESS", &mut file.compress);
+        env.set("FILE_LOG_MAX_AGE", &mut file.max_age);
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct ReadConfig
+{
+    pub interval: Duration,
+    pub retry: RetryPolicy,
+    pub deadline: Duration,
+    /// How often to look for sensors that have been added or removed, None
+    /// to only look at startup.
+    pub rescan_interval: Option<Duration>
+}
+
+#[derive(Debug, Clone)]
+pub struct TimestampConfig
+{
+    pub zone: TimeZone,
+    pub per_sensor: bool
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DeviceConfig
+{
+    pub retries: Option<u32>,
+    pub retry_backoff: Option<Duration>,
+    pub metadata: SensorMetadata
+}
+
+#[derive(Debug, Clone)]
+pub struct SensorsConfig
+{
+    pub w1_device_path: PathBuf,
+    pub am2320_device: Option<PathBuf>,
+    pub devices: BTreeMap<String, DeviceConfig>
+}
+
+static NO_METADATA: SensorMetadata = SensorMetadata {
+    alias: None,
+    location: None,
+    description: None,
+    tags: Vec::new()
+};
+
+impl SensorsConfig
+{
+    pub fn metadata(&self, id: &str) -> &SensorMetadata
+    {
+        self.devices.get(id).map_or(&NO_METADATA, |device| &device.metadata)
+    }
+
+    /// What a sensor is known by in payloads and topics: its alias if it has
+    /// one, otherwise its id.
+    pub fn key<'a>(&'a self, id: &'a str) -> &'a str
+    {
+        self.metadata(id).key(id)
+    }
+
+    /// The id of the sensor known as `name`, which may be its alias or its
+    /// id.
+    pub fn id<'a>(&'a self, name: &'a str) -> &'a str
+    {
+        self.devices.iter()
+            .find(|(_, device)| device.metadata.alias.as_deref() == Some(name))
+            .map_or(name, |(id, _)| id.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MqttConfig
+{
+    pub transport: Transport,
+    pub version: ProtocolVersion,
+    pub host: String,
+    pub port: i32,
+    /// Path of the WebSocket endpoint, only used by the ws and wss transports.
+    pub ws_path: String,
+    /// Not needed if the broker authenticates us by client certificate.
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub topic: String,
+    pub topic_layout: TopicLayout,
+    pub qos: i32,
+    /// Falls back to the system trust store if not given.
+    pub ca_cert: Option<PathBuf>,
+    /// Certificate to authenticate ourselves to the broker with (mTLS).
+    pub client_cert: Option<ClientCertConfig>,
+    /// Publish Home Assistant discovery messages for each sensor.
+    pub discovery: bool,
+    pub discovery_prefix: String,
+    /// Where to keep readings that could not be published, if anywhere.
+    pub queue: Option<QueueConfig>,
+    /// How long after being read a reading is still worth delivering. MQTT
+    /// v5 only.
+    pub message_expiry: Option<Duration>
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientCertConfig
+{
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// Only needed if the key is encrypted.
+    pub key_pass: Option<String>
+}
+
+#[derive(Debug, Clone)]
+pub struct QueueConfig
+{
+    pub dir: PathBuf,
+    pub max_bytes: u64,
+    pub max_age: Duration
+}
+
+#[derive(Debug, Clone)]
+pub struct MetricsConfig
+{
+    /// Address the Prometheus `/metrics` endpoint listens on.
+    pub listen: SocketAddr
+}
+
+/// Which InfluxDB write API to use, with what it needs to authenticate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InfluxApi
+{
+    /// InfluxDB 1.x, `/write`.
+    V1
+    {
+        database: String,
+        user: Option<String>,
+        password: Option<String>
+    },
+    /// InfluxDB 2.x, `/api/v2/write`.
+    V2
+    {
+        org: String,
+        bucket: String,
+        token: String
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InfluxConfig
+{
+    /// Base URL of the server, such as `http://influx:8086`.
+    pub url: String,
+    pub api: InfluxApi,
+    pub measurement: String,
+    /// Most lines sent in one request.
+    pub batch_size: usize,
+    /// How long to gather readings between writes. Zero writes every cycle.
+    pub flush_interval: Duration,
+    /// Attempts after the first for a write that fails on the server or
+    /// network side, with the delay doubling from `retry_backoff`.
+    pub retries: u32,
+    pub retry_backoff: Duration
+}
+
+#[derive(Debug, Clone)]
+pub struct FileLogConfig
+{
+    pub dir: PathBuf,
+    pub format: LogFormat,
+    /// Start a new file once the current one reaches this size.
+    pub max_file_bytes: u64,
+    /// Start a new file each day.
+    pub rotate_daily: bool,
+    /// Gzip files once they are finished.
+    pub compress: bool,
+    /// Delete finished files this long after they were last written.
+    pub max_age: Duration
+}
+
+#[derive(Debug, Clone)]
+pub struct ApiConfig
+{
+    /// Address the HTTP API listens on.
+    pub listen: SocketAddr
+}
+
+#[derive(Debug, Clone)]
+pub struct HistoryConfig
+{
+    /// SQLite database every reading is stored in.
+    pub path: PathBuf
+}
+
+#[derive(Debug, Clone)]
+pub struct SinksConfig
+{
+    /// None if no broker has been configured at all.
+    pub mqtt: Option<MqttConfig>,
+    /// None unless InfluxDB has been configured.
+    pub influxdb: Option<InfluxConfig>,
+    /// None unless readings are logged to local files.
+    pub file: Option<FileLogConfig>
+}
+
+impl SinksConfig
+{
+    pub fn is_empty(&self) -> bool
+    {
+        self.mqtt.is_none() && self.influxdb.is_none() && self.file.is_none()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config
+{
+    pub host: String,
+    pub read: ReadConfig,
+    pub timestamps: TimestampConfig,
+    pub sensors: SensorsConfig,
+    pub sinks: SinksConfig,
+    /// None unless the Prometheus exporter is enabled.
+    pub metrics: Option<MetricsConfig>,
+    /// None unless readings are kept in a local history database.
+    pub history: Option<HistoryConfig>,
+    /// None unless the HTTP API is enabled.
+    pub api: Option<ApiConfig>
+}
+
+
+/// Collects validation problems while resolving the raw configuration.
+struct Validator
+{
+    problems: Vec<String>
+}
+
+impl Validator
+{
+    fn required<T>(&mut self, name: &str, value: Option<T>) -> Option<T>
+    {
+        if value.is_none() {
+            self.problems.push(format!("{} is required", name));
+        }
+        value
+    }
+
+    fn check(&mut self, ok: bool, problem: impl Into<String>)
+    {
+        if !ok {
+            self.problems.push(problem.into());
+        }
+    }
+
+    fn file_exists(&mut self, name: &str, path: &Option<PathBuf>)
+    {
+        if let Some(path) = path {
+            self.check(path.is_file(), format!("{}: {} does not exist", name, path.display()));
+        }
+    }
+
+    /// A secret can be given directly, read from a file, or picked up from a
+    /// credential file if neither is set. Files are read without their
+    /// trailing newline.
+    fn secret(&mut self, name: &str, value: Option<String>,
+              file_name: &str, file: Option<PathBuf>, credential: Option<PathBuf>)
+        -> Option<String>
+    {
+        let path = match (value, file) {
+            (Some(_), Some(_)) => {
+                self.problems.push(format!("only one of {} and {} can be set", name, file_name));
+                return None;
+            },
+            (Some(value), None) => return Some(value),
+            (None, Some(path)) => path,
+            (None, None) => credential.filter(|path| path.is_file())?
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(secret) => Some(secret.trim_end_matches(&['\r', '\n'][..]).to_string()),
+            Err(e) => {
+                self.problems.push(format!("{}: cannot read {}: {}", file_name, path.display(), e));
+                None
+            }
+        }
+    }
+
+    fn seconds(&mut self, name: &str, value: f32) -> Duration
+    {
+        if value > MAX_SECONDS {
+            self.problems.push(format!("{} must be at most {} seconds", name, MAX_SECONDS));
+            return Duration::from_secs(0);
+        }
+
+        match Duration::try_from_secs_f32(value) {
+            Ok(duration) => duration,
+            Err(_) => {
+                self.problems.push(format!("{} must be a non-negative number of seconds", name));
+                Duration::from_secs(0)
+            }
+        }
+    }
+}
+
+
+impl RawMqttConfig
+{
+    fn is_empty(&self) -> bool
+    {
+        self.transport.is_none() && self.version.is_none()
+            && self.host.is_none() && self.port.is_none()
+            && self.ws_path.is_none() && self.user.is_none()
+            && self.password.is_none() && self.password_file.is_none()
+            && self.topic.is_none() && self.qos.is_none()
+            && self.topic_layout.is_none()
+            && self.ca_cert.is_none() && self.client_cert.is_none()
+            && self.client_cert_key.is_none() && self.client_cert_key_pass.is_none()
+            && self.client_cert_key_pass_file.is_none()
+            && self.discovery.is_none() && self.discovery_prefix.is_none()
+            && self.queue_dir.is_none() && self.queue_max_bytes.is_none()
+            && self.queue_max_age.is_none() && self.message_expiry.is_none()
+    }
+}
+
+/// The broker settings are optional as a whole, but once any of them are
+/// given they have to be complete.
+fn resolve_mqtt(mqtt: RawMqttConfig, credentials: Option<&Path>, v: &mut Validator)
+    -> Option<MqttConfig>
+{
+    if mqtt.is_empty() {
+        return None;
+    }
+
+    let problems = v.problems.len();
+
+    let transport = mqtt.transport.unwrap_or_default();
+    let host = v.required("sinks.mqtt.host (MQTT_HOST)", mqtt.host);
+    let port = mqtt.port.unwrap_or_else(|| transport.default_port());
+    v.check((1..=65535).contains(&port), "sinks.mqtt.port must be between 1 and 65535");
+    // Credential files are only picked up where they would be used
+    let has_user = mqtt.user.is_some();
+    let has_client_cert = mqtt.client_cert.is_some();
+    let password = v.secret(
+        "sinks.mqtt.password (MQTT_PASSWORD)", mqtt.password,
+        "sinks.mqtt.password_file (MQTT_PASSWORD_FILE)", mqtt.password_file,
+        credentials.filter(|_| has_user).map(|dir| dir.join("mqtt_password"))
+    );
+    v.check(password.is_none() || has_user,
+        "sinks.mqtt.password is set without sinks.mqtt.user (MQTT_USER)");
+    let topic = v.required("sinks.mqtt.topic (MQTT_TOPIC)", mqtt.topic);
+    let qos = mqtt.qos.unwrap_or(1);
+    v.check((0..=2).contains(&qos), "sinks.mqtt.qos must be 0, 1 or 2");
+    v.file_exists("sinks.mqtt.ca_cert", &mqtt.ca_cert);
+    v.file_exists("sinks.mqtt.client_cert", &mqtt.client_cert);
+    v.file_exists("sinks.mqtt.client_cert_key", &mqtt.client_cert_key);
+
+    if !transport.uses_tls() {
+        let tls_settings = mqtt.ca_cert.is_some() || mqtt.client_cert.is_some()
+            || mqtt.client_cert_key.is_some() || mqtt.client_cert_key_pass.is_some()
+            || mqtt.client_cert_key_pass_file.is_some();
+        v.check(!tls_settings, format!(
+            "sinks.mqtt: certificates are set, but the {} transport does not use TLS", transport
+        ));
+    }
+    if !transport.is_websocket() {
+        v.check(mqtt.ws_path.is_none(), format!(
+            "sinks.mqtt.ws_path is only used by the ws and wss transports, not {}", transport
+        ));
+    }
+
+    let version = mqtt.version.unwrap_or_default();
+    let message_expiry = mqtt.message_expiry
+        .map(|expiry| v.seconds("sinks.mqtt.message_expiry", expiry));
+    v.check(message_expiry.is_none() || version == ProtocolVersion::V5,
+        "sinks.mqtt.message_expiry needs sinks.mqtt.version (MQTT_VERSION) 5");
+    v.check(message_expiry.is_none_or(|expiry| expiry >= Duration::from_secs(1)),
+        "sinks.mqtt.message_expiry must be at least a second");
+
+    let queue_max_age = mqtt.queue_max_age
+        .map(|age| v.seconds("sinks.mqtt.queue_max_age", age))
+        .unwrap_or(DEFAULT_QUEUE_MAX_AGE);
+    let queue_max_bytes = mqtt.queue_max_bytes.unwrap_or(DEFAULT_QUEUE_MAX_BYTES);
+    let queue = mqtt.queue_dir.map(|dir| QueueConfig {
+        dir,
+        max_bytes: queue_max_bytes,
+        max_age: queue_max_age
+    });
+
+    let key_pass = v.secret(
+        "sinks.mqtt.client_cert_key_pass (CLIENT_CERT_KEY_PASS)", mqtt.client_cert_key_pass,
+        "sinks.mqtt.client_cert_key_pass_file (CLIENT_CERT_KEY_PASS_FILE)",
+        mqtt.client_cert_key_pass_file,
+        credentials.filter(|_| has_client_cert).map(|dir| dir.join("client_cert_key_pass"))
+    );
+
+    let client_cert = match (mqtt.client_cert, mqtt.client_cert_key) {
+        (Some(cert), Some(key)) => Some(ClientCertConfig { cert, key, key_pass }),
+        (Some(_), None) => {
+            v.problems.push("sinks.mqtt.client_cert is set without sinks.mqtt.client_cert_key \
+                (CLIENT_CERT_KEY)".into());
+            None
+        },
+        (None, Some(_)) => {
+            v.problems.push("sinks.mqtt.client_cert_key is set without sinks.mqtt.client_cert \
+                (CLIENT_CERT)".into());
+            None
+        },
+        (None, None) => {
+            v.check(key_pass.is_none(), "sinks.mqtt.client_cert_key_pass is set \
+                without a client certificate and key");
+            None
+        }
+    };
+
+    if v.problems.len() > problems {
+        return None;
+    }
+
+    Some(MqttConfig {
+        transport,
+        version,
+        host: host?,
+        port,
+        ws_path: mqtt.ws_path.unwrap_or_else(|| "/mqtt".into()),
+        user: mqtt.user,
+        password,
+        topic: topic?,
+        topic_layout: mqtt.topic_layout.unwrap_or_default(),
+        qos,
+        ca_cert: mqtt.ca_cert,
+        client_cert,
+        discovery: mqtt.discovery.unwrap_or(false),
+        discovery_prefix: mqtt.discovery_prefix
+            .unwrap_or_else(|| discovery::DEFAULT_PREFIX.into()),
+        queue,
+        message_expiry
+    })
+}
+
+
+impl RawInfluxConfig
+{
+    fn is_empty(&self) -> bool
+    {
+        self.url.is_none() && self.database.is_none() && self.user.is_none()
+            && self.password.is_none() && self.password_file.is_none()
+            && self.org.is_none() && self.bucket.is_none()
+            && self.token.is_none() && self.token_file.is_none()
+            && self.measurement.is_none() && self.batch_size.is_none()
+            && self.flush_interval.is_none() && self.retries.is_none()
+            && self.retry_backoff.is_none()
+    }
+}
+
+fn resolve_influxdb(influxdb: RawInfluxConfig, credentials: Option<&Path>, v: &mut Validator)
+    -> Option<InfluxConfig>
+{
+    if influxdb.is_empty() {
+        return None;
+    }
+
+    let problems = v.problems.len();
+
+    let url = v.required("sinks.influxdb.url (INFLUXDB_URL)", influxdb.url);
+    if let Some(ref url) = url {
+        v.check(url.starts_with("http://") || url.starts_with("https://"),
+            format!("sinks.influxdb.url must be an http:// or https:// URL, not '{}'", url));
+    }
+
+    let api = match (influxdb.database, influxdb.bucket) {
+        (Some(database), None) => {
+            let has_user = influxdb.user.is_some();
+            v.check(influxdb.org.is_none() && influxdb.token.is_none() && influxdb.token_file.is_none(),
+                "sinks.influxdb: org and token are only used with a bucket (InfluxDB 2)");
+            let password = v.secret(
+                "sinks.influxdb.password (INFLUXDB_PASSWORD)", influxdb.password,
+                "sinks.influxdb.password_file (INFLUXDB_PASSWORD_FILE)", influxdb.password_file,
+                credentials.filter(|_| has_user).map(|dir| dir.join("influxdb_password"))
+            );
+            v.check(password.is_none() || influxdb.user.is_some(),
+                "sinks.influxdb.password is set without sinks.influxdb.user (INFLUXDB_USER)");
+            Some(InfluxApi::V1 { database, user: influxdb.user, password })
+        },
+        (None, Some(bucket)) => {
+            v.check(influxdb.user.is_none() && influxdb.password.is_none()
+                && influxdb.password_file.is_none(),
+                "sinks.influxdb: user and password are only used with a database (InfluxDB 1)");
+            let org = v.required("sinks.influxdb.org (INFLUXDB_ORG)", influxdb.org);
+            let token = v.secret(
+                "sinks.influxdb.token (INFLUXDB_TOKEN)", influxdb.token,
+                "sinks.influxdb.token_file (INFLUXDB_TOKEN_FILE)", influxdb.token_file,
+                credentials.map(|dir| dir.join("influxdb_token"))
+            );
+            let token = v.required("sinks.influxdb.token (INFLUXDB_TOKEN)", token);
+            match (org, token) {
+                (Some(org), Some(token)) => Some(InfluxApi::V2 { org, bucket, token }),
+                _ => None
+            }
+        },
+        (Some(_), Some(_)) => {
+            v.problems.push("sinks.influxdb: set either database (InfluxDB 1) or bucket \
+                (InfluxDB 2), not both".into());
+            None
+        },
+        (None, None) => {
+            v.problems.push("sinks.influxdb: one of database (INFLUXDB_DATABASE) or bucket \
+                (INFLUXDB_BUCKET) is required".into());
+            None
+        }
+    };
+
+    let batch_size = influxdb.batch_size.unwrap_or(5000);
+    v.check(batch_size > 0, "sinks.influxdb.batch_size must be at least 1");
+    let flush_interval = influxdb.flush_interval
+        .map(|i| v.seconds("sinks.influxdb.flush_interval", i))
+        .unwrap_or_else(|| Duration::from_secs(0));
+    let retry_backoff = influxdb.retry_backoff
+        .map(|b| v.seconds("sinks.influxdb.retry_backoff", b))
+        .unwrap_or_else(|| Duration::from_secs(1));
+
+    if v.problems.len() > problems {
+        return None;
+    }
+
+    Some(InfluxConfig {
+        url: url?,
+        api: api?,
+        measurement: influxdb.measurement.unwrap_or_else(|| "sensors".into()),
+        batch_size,
+        flush_interval,
+        retries: influxdb.retries.unwrap_or(3),
+        retry_backoff
+    })
+}
+
+
+fn resolve_file_log(file: RawFileLogConfig, v: &mut Validator) -> Option<FileLogConfig>
+{
+    let settings = file.format.is_some() || file.max_file_bytes.is_some()
+        || file.rotate_daily.is_some() || file.compress.is_some() || file.max_age.is_some();
+    let dir = match file.dir {
+        Some(dir) => dir,
+        None => {
+            v.check(!settings, "sinks.file.dir (FILE_LOG_DIR) is required to log to files");
+            return None;
+        }
+    };
+
+    let max_file_bytes = file.max_file_bytes.unwrap_or(DEFAULT_LOG_MAX_FILE_BYTES);
+    v.check(max_file_bytes > 0, "sinks.file.max_file_bytes must be greater than zero");
+    let max_age = file.max_age
+        .map(|age| v.seconds("sinks.file.max_age", age))
+        .unwrap_or(DEFAULT_LOG_MAX_AGE);
+
+    Some(FileLogConfig {
+        dir,
+        format: file.format.unwrap_or_default(),
+        max_file_bytes,
+        rotate_daily: file.rotate_daily.unwrap_or(true),
+        compress: file.compress.unwrap_or(true),
+        max_age
+    })
+}
+
+
+fn system_hostname() -> Option<String>
+{
+    fs::read_to_string("/etc/hostname").ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+
+impl Config
+{
+    /// Load the configuration file at `path`, if there is one, and apply
+    /// overrides from the process environment. A missing file is only an
+    /// error if `required` is set.
+    pub fn load(path: &Path, required: bool) -> Result<Config, ConfigError>
+    {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => Some(contents),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound && !required => None,
+            Err(e) => return Err(ConfigError::Io(path.to_path_buf(), e))
+        };
+
+        let raw = match contents {
+            Some(ref contents) => toml::from_str(contents)
+                .map_err(|e| ConfigError::Parse(path.to_path_buf(), e))?,
+            None => RawConfig::default()
+        };
+
+        Config::resolve(raw, &|name: &str| env::var(name).ok())
+    }
+
+    /// Build the configuration from the contents of a configuration file and
+    /// an environment lookup.
+    pub fn from_sources(contents: &str, env: &dyn Fn(&str) -> Option<String>)
+        -> Result<Config, ConfigError>
+    {
+        let raw = toml::from_str(contents)
+            .map_err(|e| ConfigError::Parse(PathBuf::from("<config>"), e))?;
+        Config::resolve(raw, env)
+    }
+
+    fn resolve(mut raw: RawConfig, env: &dyn Fn(&str) -> Option<String>)
+        -> Result<Config, ConfigError>
+    {
+        let mut overrides = EnvOverrides { lookup: env, problems: Vec::new() };
+        raw.apply_env(&mut overrides);
+
+        let mut v = Validator { problems: overrides.problems };
+
+        let host = v.required("host (HOSTNAME)", raw.host.or_else(system_hostname));
+
+        let interval = raw.read.interval.unwrap_or(10.0);
+        v.check(interval > 0.0, "read.interval must be greater than zero");
+        let interval = v.seconds("read.interval", interval);
+        let default_retry = RetryPolicy::default();
+        let retry = RetryPolicy::new(
+            raw.read.retries.unwrap_or(default_retry.retries),
+            raw.read.retry_backoff
+                .map(|b| v.seconds("read.retry_backoff", b))
+                .unwrap_or(default_retry.backoff)
+        );
+        // By default leave at least half the interval for publishing
+        let deadline = raw.read.deadline
+            .map(|d| v.seconds("read.deadline", d))
+            .unwrap_or(interval / 2);
+        v.check(deadline <= interval, "read.deadline must not be longer than read.interval");
+        let rescan_interval = raw.read.rescan_interval
+            .map(|i| v.seconds("read.rescan_interval", i))
+            .unwrap_or(DEFAULT_RESCAN_INTERVAL);
+        let rescan_interval = Some(rescan_interval).filter(|i| *i > Duration::from_secs(0));
+
+        let zone = match raw.timestamps.timezone {
+            Some(ref zone) => TimeZone::from_str(zone).unwrap_or_else(|e| {
+                v.problems.push(format!("timestamps.timezone: {}", e));
+                TimeZone::Utc
+            }),
+            None => TimeZone::Utc
+        };
+
+        let mut devices = BTreeMap::new();
+        let mut aliases = BTreeMap::new();
+        let ids: Vec<String> = raw.sensors.devices.keys().cloned().collect();
+        for (id, device) in raw.sensors.devices {
+            let retry_backoff = device.retry_backoff
+                .map(|b| v.seconds(&format!("sensors.devices.{}.retry_backoff", id), b));
+
+            if let Some(ref alias) = device.alias {
+                // The alias ends up as a topic level
+                v.check(
+                    !alias.is_empty() && !alias.contains(&['/', '+', '#'][..]),
+                    format!("sensors.devices.{}.alias must be non-empty and not contain /, + or #", id)
+                );
+                v.check(
+                    !RESERVED_ALIASES.contains(&alias.as_str()),
+                    format!("sensors.devices.{}.alias must not be one of {}", id, RESERVED_ALIASES.join(", "))
+                );
+                if let Some(other) = aliases.insert(alias.clone(), id.clone()) {
+                    v.problems.push(format!("sensors.devices.{}.alias is also the alias of {}", id, other));
+                }
+                if *alias != id && ids.contains(alias) {
+                    v.problems.push(format!("sensors.devices.{}.alias is the id of another sensor", id));
+                } else if *alias != id && SENSOR_ID_PREFIXES.iter().any(|p| alias.starts_with(p)) {
+                    v.problems.push(format!(
+                        "sensors.devices.{}.alias must not start with {}, as sensor ids do",
+                        id, SENSOR_ID_PREFIXES.join(" or ")
+                    ));
+                }
+            }
+
+            let metadata = SensorMetadata {
+                alias: device.alias,
+                location: device.location,
+                description: device.description,
+                tags: device.tags.unwrap_or_default()
+            };
+            devices.insert(id, DeviceConfig { retries: device.retries, retry_backoff, metadata });
+        }
+
+        // Secrets passed with systemd's LoadCredential=
+        let credentials = env("CREDENTIALS_DIRECTORY").map(PathBuf::from);
+        let mqtt = resolve_mqtt(raw.sinks.mqtt, credentials.as_deref(), &mut v);
+        let influxdb = resolve_influxdb(raw.sinks.influxdb, credentials.as_deref(), &mut v);
+        let file = resolve_file_log(raw.sinks.file, &mut v);
+
+        if !v.problems.is_empty() {
+            return Err(ConfigError::Invalid(v.problems));
+        }
+
+        // Everything required is present, otherwise there would be problems
+        Ok(Config {
+            host: host.unwrap(),
+            read: ReadConfig { interval, retry, deadline, rescan_interval },
+            timestamps: TimestampConfig {
+                zone,
+                per_sensor: raw.timestamps.per_sensor.unwrap_or(false)
+            },
+            sensors: SensorsConfig {
+                w1_device_path: raw.sensors.w1_device_path
+                    .unwrap_or_else(|| PathBuf::from(ds18b20::DEFAULT_DEVICE_PATH)),
+                am2320_device: raw.sensors.am2320_device,
+                devices
+            },
+            sinks: SinksConfig {
+                mqtt,
+                influxdb,
+                file
+            },
+            metrics: raw.metrics.listen.map(|listen| MetricsConfig { listen }),
+            history: raw.history.path.map(|path| HistoryConfig { path }),
+            api: raw.api.listen.map(|listen| ApiConfig { listen })
+        })
+    }
+
+    /// The retry policy for a particular sensor, taking any per-device
+    /// settings into account.
+    pub fn retry_policy(&self, id: &str) -> RetryPolicy
+    {
+        let mut policy = self.read.retry.clone();
+        if let Some(device) = self.sensors.devices.get(id) {
+            if let Some(retries) = device.retries {
+                policy.retries = retries;
+            }
+            if let Some(backoff) = device.retry_backoff {
+                policy.backoff = backoff;
+            }
+        }
+        policy
+    }
+}
@@ -0,0 +1,164 @@
+
+//! Home Assistant MQTT discovery, so that sensors show up in Home Assistant
+//! without having to write a template sensor for each of them.
+//!
+//! See https://www.home-assistant.io/docs/mqtt/discovery/
+
+use serde::Serialize;
+
+use crate::layout::TopicLayout;
+use crate::mqtt::{metadata_topic, status_topic};
+use crate::sensors::{Sensor, SensorMetadata, QuantityKind};
+
+
+pub const DEFAULT_PREFIX: &str = "homeassistant";
+
+
+#[derive(Serialize, Debug)]
+struct Device
+{
+    identifiers: Vec<String>,
+    name: String,
+    model: &'static str,
+    sw_version: &'static str
+}
+
+#[derive(Serialize, Debug)]
+struct SensorConfig
+{
+    name: String,
+    unique_id: String,
+    state_topic: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value_template: Option<String>,
+    availability_topic: String,
+    /// The sensor's location, description and tags show up as attributes.
+    json_attributes_topic: String,
+    unit_of_measurement: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device_class: Option<&'static str>,
+    state_class: &'static str,
+    device: Device
+}
+
+
+/// Home Assistant device class for a quantity, where it has one.
+fn device_class(kind: &QuantityKind) -> Option<&'static str>
+{
+    match kind.name {
+        "temperature" => Some("temperature"),
+        "humidity" => Some("humidity"),
+        _ => None
+    }
+}
+
+/// Template that picks the value of `kind` out of the list of quantities at
+/// `quantities`. A sensor that failed to read has no quantities, which
+/// leaves the entity without a value rather than failing the template.
+fn value_template(quantities: &str, kind: &QuantityKind) -> String
+{
+    format!(
+        "{{{{ {} | default([]) | selectattr('name', 'eq', '{}') \
+         | map(attribute='value') | first | default(none) }}}}",
+        quantities, kind.name
+    )
+}
+
+/// `s` as a string literal in a template.
+fn template_string(s: &str) -> String
+{
+    format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+/// Discovery topics only allow `[a-zA-Z0-9_-]` in the node and object ids.
+pub fn sanitize_id(id: &str) -> String
+{
+    id.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+
+/// Builds the retained discovery messages for the sensors on one node.
+pub struct Discovery<'a>
+{
+    prefix: &'a str,
+    host: &'a str,
+    state_topic: &'a str,
+    layout: TopicLayout
+}
+
+impl<'a> Discovery<'a>
+{
+    pub fn new(prefix: &'a str, host: &'a str, state_topic: &'a str) -> Discovery<'a>
+    {
+        Discovery { prefix, host, state_topic, layout: TopicLayout::default() }
+    }
+
+    /// Point Home Assistant at the topics readings are published to. The
+    /// per-quantity topics are preferred, as they carry a plain value, then
+    /// the per-sensor ones, then the aggregate.
+    pub fn with_layout(mut self, layout: TopicLayout) -> Discovery<'a>
+    {
+        self.layout = layout;
+        self
+    }
+
+    fn device(&self) -> Device
+    {
+        Device {
+            identifiers: vec![format!("sensor_reader_{}", sanitize_id(self.host))],
+            name: self.host.into(),
+            model: "sensor_reader",
+            sw_version: env!("CARGO_PKG_VERSION")
+        }
+    }
+
+    /// The topic and payload of each discovery message for `sensor`. Sensors
+    /// that measure a single quantity use the sensor id as the object id,
+    /// the others get one entity per quantity, suffixed with its name. The
+    /// id stays the object id if the sensor has an alias, so that renaming
+    /// it doesn't create new entities, but the state topics and entity
+    /// names follow the alias.
+    pub fn messages(&self, sensor: &dyn Sensor, metadata: &SensorMetadata) -> Vec<(String, String)>
+    {
+        let node = sanitize_id(self.host);
+        let id = sensor.identifier();
+        let key = metadata.key(id);
+        let quantities = sensor.quantities();
+
+        quantities.iter().map(|kind| {
+            let object_id = if quantities.len() == 1 {
+                sanitize_id(id)
+            } else {
+                sanitize_id(&format!("{}_{}", id, kind.name))
+            };
+
+            let (state_topic, value_template) = if self.layout.per_quantity {
+                (TopicLayout::quantity_topic(self.state_topic, key, kind.name), None)
+            } else if self.layout.per_sensor {
+                (TopicLayout::sensor_topic(self.state_topic, key),
+                 Some(value_template("value_json.quantities", kind)))
+            } else {
+                (self.state_topic.into(),
+                 Some(value_template(&format!("value_json.sensors[{}].quantities", template_string(key)), kind)))
+            };
+
+            let config = SensorConfig {
+                name: format!("{} {} {}", self.host, key, kind.name),
+                unique_id: format!("{}_{}", node, object_id),
+                state_topic,
+                value_template,
+                availability_topic: status_topic(self.state_topic),
+                json_attributes_topic: metadata_topic(self.state_topic, key),
+                unit_of_measurement: kind.unit,
+                device_class: device_class(kind),
+                state_class: "measurement",
+                device: self.device()
+            };
+
+            let topic = format!("{}/sensor/{}/{}/config", self.prefix, node, object_id);
+            (topic, serde_json::to_string(&config).unwrap())
+        }).collect()
+    }
+}
@@ -0,0 +1,294 @@
+
+//! Appends every read cycle to a local file, for sites without a network.
+//!
+//! Files are named `readings-<date>-<sequence>.<csv|ndjson>`, in the time
+//! zone timestamps are rendered in. A new file is started each day and
+//! whenever the current one reaches its size limit. Finished files are
+//! gzipped, and deleted once they are older than the retention limit.
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Deserialize;
+
+use crate::config::FileLogConfig;
+use crate::payload::Payload;
+use crate::sensors::SensorReport;
+
+
+const PREFIX: &str = "readings-";
+
+const CSV_HEADER: &str = "timestamp,timestamp_ms,sensor_id,alias,type,status,quantity,value,unit,error\n";
+
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat
+{
+    /// A row per quantity, or per failed sensor.
+    Csv,
+    /// The JSON payload of each cycle on a line of its own.
+    #[default]
+    Ndjson
+}
+
+impl LogFormat
+{
+    pub fn extension(&self) -> &'static str
+    {
+        match self {
+            LogFormat::Csv => "csv",
+            LogFormat::Ndjson => "ndjson"
+        }
+    }
+}
+
+impl FromStr for LogFormat
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LogFormat, String>
+    {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(LogFormat::Csv),
+            "ndjson" | "jsonl" => Ok(LogFormat::Ndjson),
+            _ => Err(format!("unknown log format '{}', expected csv or ndjson", s))
+        }
+    }
+}
+
+impl fmt::Display for LogFormat
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "{}", self.extension())
+    }
+}
+
+
+/// Quotes a CSV field if it needs it.
+fn csv_field(s: &str) -> String
+{
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// The CSV rows for one cycle. The alias column is empty for sensors
+/// without one.
+pub fn csv_rows(payload: &Payload) -> String
+{
+    let mut rows = String::new();
+
+    for (key, entry) in &payload.sensors {
+        let time = entry.time.as_ref().unwrap_or(&payload.time);
+        let prefix = format!(
+            "{},{},{},{},{}",
+            csv_field(&time.timestamp), time.timestamp_ms, csv_field(entry.sensor_id(key)),
+            csv_field(entry.alias(key).unwrap_or("")), entry.report.sensor_type()
+        );
+
+        match entry.report {
+            SensorReport::Ok(ref reading) => {
+                for quantity in &reading.quantities {
+                    rows.push_str(&format!(
+                        "{},ok,{},{},{},\n",
+                        prefix, quantity.name, quantity.value, csv_field(quantity.unit)
+                    ));
+                }
+            },
+            SensorReport::Error { error, .. } => {
+                rows.push_str(&format!("{},error,,,,{}\n", prefix, error));
+            }
+        }
+    }
+
+    rows
+}
+
+
+struct LogFile
+{
+    path: PathBuf,
+    date: String,
+    sequence: u32,
+    compressed: bool
+}
+
+struct Current
+{
+    date: String,
+    file: File,
+    size: u64
+}
+
+
+/// Appends each cycle to the current log file, rotating, compressing and
+/// expiring files as it goes.
+pub struct FileLogger<'a>
+{
+    config: &'a FileLogConfig,
+    current: Option<Current>
+}
+
+impl<'a> FileLogger<'a>
+{
+    /// Create the log directory if needed. Nothing is opened until the first
+    /// cycle is written.
+    pub fn open(config: &'a FileLogConfig) -> io::Result<FileLogger<'a>>
+    {
+        fs::create_dir_all(&config.dir)?;
+        Ok(FileLogger { config, current: None })
+    }
+
+    fn parse_name(&self, path: &Path) -> Option<LogFile>
+    {
+        let name = path.file_name()?.to_str()?.strip_prefix(PREFIX)?;
+        let (name, compressed) = match name.strip_suffix(".gz") {
+            Some(name) => (name, true),
+            None => (name, false)
+        };
+        let stem = name.strip_suffix(self.config.format.extension())?.strip_suffix('.')?;
+        let split = stem.rfind('-')?;
+
+        Some(LogFile {
+            path: path.to_path_buf(),
+            date: stem[..split].to_string(),
+            sequence: stem[split + 1..].parse().ok()?,
+            compressed
+        })
+    }
+
+    fn log_files(&self) -> io::Result<Vec<LogFile>>
+    {
+        let mut files = Vec::new();
+        for item in fs::read_dir(&self.config.dir)? {
+            if let Some(file) = self.parse_name(&item?.path()) {
+                files.push(file);
+            }
+        }
+        Ok(files)
+    }
+
+    fn path(&self, date: &str, sequence: u32) -> PathBuf
+    {
+        let extension = self.config.format.extension();
+        self.config.dir.join(format!("{}{}-{:03}.{}", PREFIX, date, sequence, extension))
+    }
+
+    /// Gzip a finished file, replacing it.
+    fn compress(path: &Path) -> io::Result<()>
+    {
+        let mut gz_path = path.as_os_str().to_owned();
+        gz_path.push(".gz");
+        let gz_path = PathBuf::from(gz_path);
+        let tmp = gz_path.with_extension("gz.tmp");
+
+        let mut encoder = GzEncoder::new(File::create(&tmp)?, Compression::default());
+        io::copy(&mut File::open(path)?, &mut encoder)?;
+        encoder.finish()?.sync_all()?;
+
+        fs::rename(&tmp, &gz_path)?;
+        fs::remove_file(path)
+    }
+
+    /// Delete finished files that are past the retention limit.
+    fn prune(&self, current: &Path) -> io::Result<()>
+    {
+        let now = SystemTime::now();
+
+        for file in self.log_files()? {
+            if file.path == current {
+                continue;
+            }
+            let modified = fs::metadata(&file.path)?.modified()?;
+            let age = now.duration_since(modified).unwrap_or_default();
+            if age >= self.config.max_age {
+                fs::remove_file(&file.path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Switch to the log file for `date`, carrying on with the latest one for
+    /// that day if it still has room. Every other file is finished.
+    fn start(&mut self, date: &str) -> io::Result<()>
+    {
+        self.current = None;
+
+        let files = self.log_files()?;
+        let latest = files.iter()
+            .filter(|f| f.date == date)
+            .max_by_key(|f| f.sequence);
+        let sequence = match latest {
+            Some(f) => {
+                let has_room = !f.compressed
+                    && fs::metadata(&f.path)?.len() < self.config.max_file_bytes;
+                if has_room { f.sequence } else { f.sequence + 1 }
+            },
+            None => 0
+        };
+        let path = self.path(date, sequence);
+
+        if self.config.compress {
+            for file in files.iter().filter(|f| !f.compressed && f.path != path) {
+                FileLogger::compress(&file.path)?;
+            }
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let mut size = file.metadata()?.len();
+        if size == 0 && self.config.format == LogFormat::Csv {
+            file.write_all(CSV_HEADER.as_bytes())?;
+            size = CSV_HEADER.len() as u64;
+        }
+
+        self.current = Some(Current { date: date.to_string(), file, size });
+        self.prune(&path)
+    }
+
+    fn append(&mut self, payload: &Payload) -> io::Result<()>
+    {
+        let text = match self.config.format {
+            LogFormat::Csv => csv_rows(payload),
+            LogFormat::Ndjson => payload.to_json()? + "\n"
+        };
+        // RFC3339, so this is the date in the configured time zone
+        let date = &payload.time.timestamp[..10];
+
+        let rotate = match self.current {
+            Some(ref current) => (self.config.rotate_daily && current.date != date)
+                || current.size >= self.config.max_file_bytes,
+            None => true
+        };
+        if rotate {
+            self.start(date)?;
+        }
+
+        let current = self.current.as_mut().unwrap();
+        current.file.write_all(text.as_bytes())?;
+        current.file.flush()?;
+        current.size += text.len() as u64;
+        Ok(())
+    }
+
+    /// Append the readings from one cycle.
+    pub fn write(&mut self, payload: &Payload) -> io::Result<()>
+    {
+        let result = self.append(payload);
+        if result.is_err() {
+            // Start afresh next time, in case the file has gone away
+            self.current = None;
+        }
+        result
+    }
+}
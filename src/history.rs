@@ -0,0 +1,188 @@
+
+//! A local SQLite store of every reading, so the history can be looked at on
+//! the Pi itself, even if it was offline at the time.
+
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::{DateTime, Local, NaiveDate, TimeZone as _, Utc};
+use rusqlite::{params, Connection};
+
+use crate::payload::{Payload, TimeZone};
+use crate::sensors::SensorReport;
+
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS readings (
+    sensor_id TEXT NOT NULL,
+    quantity TEXT NOT NULL,
+    timestamp_ms INTEGER NOT NULL,
+    sensor_type TEXT NOT NULL,
+    value REAL NOT NULL,
+    unit TEXT NOT NULL,
+    alias TEXT,
+    PRIMARY KEY (sensor_id, quantity, timestamp_ms)
+) WITHOUT ROWID;
+";
+
+
+/// Readings of one quantity over one bucket of time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bucket
+{
+    pub quantity: String,
+    pub unit: String,
+    /// Start of the bucket, in milliseconds since the Unix epoch.
+    pub start_ms: i64,
+    pub count: i64,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64
+}
+
+
+pub struct History
+{
+    connection: Connection
+}
+
+impl History
+{
+    /// Open the database at `path`, creating it if needed.
+    pub fn open(path: &Path) -> rusqlite::Result<History>
+    {
+        let connection = Connection::open(path)?;
+        // Lets `history` read while `run` is writing
+        connection.pragma_update(None, "journal_mode", &"WAL")?;
+        connection.execute_batch(SCHEMA)?;
+
+        // Databases from before aliases were recorded lack the column
+        let has_alias = connection.prepare("SELECT alias FROM readings LIMIT 0").is_ok();
+        if !has_alias {
+            connection.execute_batch("ALTER TABLE readings ADD COLUMN alias TEXT")?;
+        }
+
+        Ok(History { connection })
+    }
+
+    /// Store a row per quantity of every sensor that was read successfully,
+    /// under the sensor's id, with its alias alongside. Returns the number of
+    /// rows stored.
+    pub fn record(&mut self, payload: &Payload) -> rusqlite::Result<usize>
+    {
+        let transaction = self.connection.transaction()?;
+        let mut rows = 0;
+
+        {
+            let mut insert = transaction.prepare_cached(
+                "INSERT OR REPLACE INTO readings
+                 (sensor_id, quantity, timestamp_ms, sensor_type, value, unit, alias)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)"
+            )?;
+
+            for (key, entry) in &payload.sensors {
+                let reading = match entry.report {
+                    SensorReport::Ok(ref reading) => reading,
+                    SensorReport::Error { .. } => continue
+                };
+                let time = entry.time.as_ref().unwrap_or(&payload.time);
+
+                for quantity in &reading.quantities {
+                    rows += insert.execute(params![
+                        entry.sensor_id(key), quantity.name, time.timestamp_ms, reading.sensor_type,
+                        f64::from(quantity.value), quantity.unit, entry.alias(key)
+                    ])?;
+                }
+            }
+        }
+
+        transaction.commit()?;
+        Ok(rows)
+    }
+
+    /// Summarise the readings of `sensor_id` between `from` (inclusive) and
+    /// `to` (exclusive), in buckets of `bucket` counted from `from`. Only
+    /// `quantity` is included if it is given. Buckets without readings are
+    /// left out.
+    pub fn query(&self, sensor_id: &str, quantity: Option<&str>,
+                 from: DateTime<Utc>, to: DateTime<Utc>, bucket: Duration)
+        -> rusqlite::Result<Vec<Bucket>>
+    {
+        let from_ms = from.timestamp_millis();
+        let bucket_ms = (bucket.as_millis() as i64).max(1);
+
+        let mut statement = self.connection.prepare_cached(
+            "SELECT quantity, unit, (timestamp_ms - ?2) / ?4 AS bucket,
+                    COUNT(*), MIN(value), MAX(value), AVG(value)
+             FROM readings
+             WHERE sensor_id = ?1 AND timestamp_ms >= ?2 AND timestamp_ms < ?3
+               AND (?5 IS NULL OR quantity = ?5)
+             GROUP BY quantity, unit, bucket
+             ORDER BY quantity, bucket"
+        )?;
+
+        let rows = statement.query_map(
+            params![sensor_id, from_ms, to.timestamp_millis(), bucket_ms, quantity],
+            |row| Ok(Bucket {
+                quantity: row.get(0)?,
+                unit: row.get(1)?,
+                start_ms: from_ms + row.get::<_, i64>(2)? * bucket_ms,
+                count: row.get(3)?,
+                min: row.get(4)?,
+                max: row.get(5)?,
+                mean: row.get(6)?
+            })
+        )?;
+
+        rows.collect()
+    }
+}
+
+
+/// Parses a length of time such as `90s`, `15m`, `1h` or `7d`. A bare
+/// number is taken as seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String>
+{
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+
+    let number: u64 = number.parse()
+        .map_err(|_| format!("invalid duration '{}', expected for example 30m, 1h or 2d", s))?;
+    let seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("unknown unit in duration '{}', expected s, m, h or d", s))
+    };
+
+    number.checked_mul(seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("duration '{}' is too long", s))
+}
+
+/// Parses a point in time given as RFC3339, as a date (its midnight in
+/// `zone`), or as a duration before `now`, such as `24h`.
+pub fn parse_time(s: &str, zone: TimeZone, now: DateTime<Utc>) -> Result<DateTime<Utc>, String>
+{
+    if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+        return Ok(time.with_timezone(&Utc));
+    }
+
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        let midnight = date.and_hms(0, 0, 0);
+        return match zone {
+            TimeZone::Utc => Ok(Utc.from_utc_datetime(&midnight)),
+            TimeZone::Local => Local.from_local_datetime(&midnight).earliest()
+                .map(|time| time.with_timezone(&Utc))
+                .ok_or_else(|| format!("{} has no midnight in the local time zone", s))
+        };
+    }
+
+    let ago = parse_duration(s)
+        .map_err(|_| format!("invalid time '{}', expected RFC3339, YYYY-MM-DD or a duration ago", s))?;
+    chrono::Duration::from_std(ago).ok()
+        .and_then(|ago| now.checked_sub_signed(ago))
+        .ok_or_else(|| format!("{} ago is too long ago", s))
+}
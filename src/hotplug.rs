@@ -0,0 +1,72 @@
+
+//! Picks up sensors that are added to or removed from the bus while we are
+//! running, by scanning for them again every so often. sysfs doesn't reliably
+//! report new 1-Wire slaves through inotify, so this polls.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::payload::Timestamp;
+use crate::sensors::{Sensor, SensorList};
+
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind
+{
+    Added,
+    Removed
+}
+
+impl fmt::Display for EventKind
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self {
+            EventKind::Added => write!(f, "added"),
+            EventKind::Removed => write!(f, "removed")
+        }
+    }
+}
+
+/// A sensor appearing or disappearing, as published on the events topic.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct SensorEvent
+{
+    pub event: EventKind,
+    pub id: String,
+    #[serde(rename = "type")]
+    pub sensor_type: &'static str,
+    #[serde(flatten)]
+    pub time: Timestamp
+}
+
+/// Replace `current` with the sensors from a fresh scan, returning an event
+/// for each sensor that was added or removed. Removals come first.
+pub fn rescan(current: &mut SensorList, found: SensorList, time: &Timestamp) -> Vec<SensorEvent>
+{
+    let current_ids: BTreeSet<&str> = current.iter().map(|s| s.identifier()).collect();
+    let found_ids: BTreeSet<&str> = found.iter().map(|s| s.identifier()).collect();
+
+    let event = |event, sensor: &dyn Sensor| SensorEvent {
+        event,
+        id: sensor.identifier().into(),
+        sensor_type: sensor.sensor_type(),
+        time: time.clone()
+    };
+
+    let mut events: Vec<SensorEvent> = current.iter()
+        .filter(|s| !found_ids.contains(s.identifier()))
+        .map(|s| event(EventKind::Removed, s.as_ref()))
+        .collect();
+    events.extend(found.iter()
+        .filter(|s| !current_ids.contains(s.identifier()))
+        .map(|s| event(EventKind::Added, s.as_ref())));
+
+    if !events.is_empty() {
+        *current = found;
+    }
+    events
+}
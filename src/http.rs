@@ -0,0 +1,39 @@
+
+//! The small HTTP server behind the API and the metrics endpoint.
+
+use std::error::Error;
+use std::io::Cursor;
+use std::net::SocketAddr;
+use std::thread;
+
+
+pub type Response = tiny_http::Response<Cursor<Vec<u8>>>;
+
+/// A header from a name and value known to be valid.
+pub fn header(name: &str, value: &str) -> tiny_http::Header
+{
+    tiny_http::Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap()
+}
+
+/// Serve `handler` on `listen` from a background thread, for as long as the
+/// process runs. The handler is given the method and the path of each
+/// request, without its query string.
+pub fn serve<F>(listen: SocketAddr, handler: F) -> Result<(), Box<dyn Error>>
+    where F: Fn(&str, &str) -> Response + Send + 'static
+{
+    let server = tiny_http::Server::http(listen)
+        .map_err(|e| format!("could not listen on {}: {}", listen, e))?;
+
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let path = request.url().split('?').next().unwrap_or("").to_string();
+            let response = handler(request.method().as_str(), &path);
+
+            if let Err(e) = request.respond(response) {
+                eprintln!("An error occurred answering a request on {} {:?}", listen, e);
+            }
+        }
+    });
+
+    Ok(())
+}
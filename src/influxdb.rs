@@ -0,0 +1,255 @@
+
+//! Writes readings to InfluxDB in line protocol, through either the 1.x
+//! `/write` or the 2.x `/api/v2/write` endpoint.
+//!
+//! See https://docs.influxdata.com/influxdb/v2.0/reference/syntax/line-protocol/
+
+use std::collections::VecDeque;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use base64::Engine as _;
+
+use crate::config::{InfluxApi, InfluxConfig};
+use crate::payload::Payload;
+use crate::sensors::SensorReport;
+
+
+/// How long to wait for the server to answer a write.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many batches worth of lines to hold on to while the server can't be
+/// reached. The oldest are dropped beyond that.
+const MAX_BUFFERED_BATCHES: usize = 10;
+
+
+/// Measurements escape commas and spaces.
+fn escape_measurement(s: &str) -> String
+{
+    s.replace('\\', "\\\\").replace(',', "\\,").replace(' ', "\\ ")
+}
+
+/// Tag keys, tag values and field keys also escape equals signs.
+fn escape_key(s: &str) -> String
+{
+    escape_measurement(s).replace('=', "\\=")
+}
+
+/// One line per sensor that was read successfully, with the host and sensor
+/// id as tags, a field per quantity and a nanosecond timestamp:
+///
+/// `sensors,host=barn-pi,sensor_id=am2320-i2c-1,type=am2320 temperature=21.5,humidity=40 1618920000000000000`
+///
+/// A sensor with an alias also gets an `alias` tag. Its series stays keyed
+/// by the id, so renaming the alias doesn't split it.
+pub fn line_protocol(measurement: &str, host: &str, payload: &Payload) -> Vec<String>
+{
+    let mut lines = Vec::new();
+
+    for (key, entry) in &payload.sensors {
+        // Line protocol has no way to say a reading is missing, other than
+        // leaving it out
+        let reading = match entry.report {
+            SensorReport::Ok(ref reading) if !reading.quantities.is_empty() => reading,
+            _ => continue
+        };
+
+        let fields: Vec<String> = reading.quantities.iter()
+            .map(|q| format!("{}={}", escape_key(q.name), q.value))
+            .collect();
+        let time = entry.time.as_ref().unwrap_or(&payload.time);
+        let alias = entry.alias(key)
+            .map_or(String::new(), |alias| format!(",alias={}", escape_key(alias)));
+
+        lines.push(format!(
+            "{}{},host={},sensor_id={},type={} {} {}",
+            escape_measurement(measurement),
+            alias,
+            escape_key(host),
+            escape_key(entry.sensor_id(key)),
+            escape_key(reading.sensor_type),
+            fields.join(","),
+            time.timestamp_ms * 1_000_000
+        ));
+    }
+
+    lines
+}
+
+
+enum Outcome
+{
+    Written,
+    /// The server refused the data, sending it again won't help.
+    Rejected(String),
+    /// The server or the network failed, it may work later.
+    Failed(String)
+}
+
+
+/// Buffers lines and writes them in batches, retrying writes that fail on
+/// the server or network side. Lines that still can't be written are kept
+/// for the next flush.
+pub struct InfluxWriter<'a>
+{
+    config: &'a InfluxConfig,
+    host: &'a str,
+    agent: ureq::Agent,
+    buffer: VecDeque<String>,
+    /// How many of the oldest buffered lines have already been counted as
+    /// not written, so that they aren't counted again every cycle while the
+    /// server is down.
+    reported: usize,
+    last_flush: Instant
+}
+
+impl<'a> InfluxWriter<'a>
+{
+    pub fn new(config: &'a InfluxConfig, host: &'a str) -> InfluxWriter<'a>
+    {
+        InfluxWriter {
+            config,
+            host,
+            agent: ureq::AgentBuilder::new().timeout(TIMEOUT).build(),
+            buffer: VecDeque::new(),
+            reported: 0,
+            last_flush: Instant::now()
+        }
+    }
+
+    /// Number of lines waiting to be written.
+    pub fn buffered(&self) -> usize
+    {
+        self.buffer.len()
+    }
+
+    fn request(&self) -> ureq::Request
+    {
+        let base = self.config.url.trim_end_matches('/');
+
+        let request = match self.config.api {
+            InfluxApi::V1 { ref database, ref user, ref password } => {
+                let request = self.agent.post(&format!("{}/write", base))
+                    .query("db", database)
+                    .query("precision", "n");
+                // Credentials go in a header rather than the u and p query
+                // parameters, which would end up in logged error messages
+                match user {
+                    Some(user) => {
+                        let credentials = format!("{}:{}", user, password.as_deref().unwrap_or(""));
+                        let encoded = base64::engine::general_purpose::STANDARD.encode(credentials);
+                        request.set("Authorization", &format!("Basic {}", encoded))
+                    },
+                    None => request
+                }
+            },
+            InfluxApi::V2 { ref org, ref bucket, ref token } => {
+                self.agent.post(&format!("{}/api/v2/write", base))
+                    .query("org", org)
+                    .query("bucket", bucket)
+                    .query("precision", "ns")
+                    .set("Authorization", &format!("Token {}", token))
+            }
+        };
+
+        request.set("Content-Type", "text/plain; charset=utf-8")
+    }
+
+    /// Send one batch, retrying while there is time before `deadline`.
+    fn send(&self, body: &str, deadline: Instant) -> Outcome
+    {
+        let mut delay = self.config.retry_backoff;
+        let mut attempt = 0;
+
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left == Duration::from_secs(0) {
+                return Outcome::Failed("out of time for this cycle".into());
+            }
+
+            let error = match self.request().timeout(left.min(TIMEOUT)).send_string(body) {
+                Ok(_) => return Outcome::Written,
+                // Retrying bad data or bad credentials only makes more noise
+                Err(ureq::Error::Status(status, response)) if status < 500 && status != 429 => {
+                    let message = response.into_string().unwrap_or_default();
+                    return Outcome::Rejected(format!("status {}: {}", status, message.trim()));
+                },
+                Err(e) => e.to_string()
+            };
+
+            if attempt >= self.config.retries || Instant::now() + delay >= deadline {
+                return Outcome::Failed(error);
+            }
+
+            eprintln!("Retrying InfluxDB write after error: {}", error);
+            thread::sleep(delay);
+
+            attempt += 1;
+            delay *= 2;
+        }
+    }
+
+    /// Drop the `count` oldest buffered lines. Returns how many of them had
+    /// not been counted as not written yet.
+    fn drop_oldest(&mut self, count: usize) -> usize
+    {
+        self.buffer.drain(..count);
+        let reported = count.min(self.reported);
+        self.reported -= reported;
+        count - reported
+    }
+
+    /// Write everything buffered, a batch at a time, giving up on whatever
+    /// is left at `deadline`. Returns the number of lines that could not be
+    /// written, counting each line only the first time it isn't.
+    pub fn flush(&mut self, deadline: Instant) -> usize
+    {
+        self.last_flush = Instant::now();
+        let mut failed = 0;
+
+        while !self.buffer.is_empty() {
+            let count = self.buffer.len().min(self.config.batch_size);
+            let body = self.buffer.iter().take(count).cloned().collect::<Vec<_>>().join("\n");
+
+            match self.send(&body, deadline) {
+                Outcome::Written => {
+                    self.drop_oldest(count);
+                },
+                Outcome::Rejected(e) => {
+                    eprintln!("InfluxDB rejected {} lines, dropping them: {}", count, e);
+                    failed += self.drop_oldest(count);
+                },
+                Outcome::Failed(e) => {
+                    eprintln!("An error occurred writing to InfluxDB: {}", e);
+                    failed += self.buffer.len() - self.reported;
+                    self.reported = self.buffer.len();
+                    return failed;
+                }
+            }
+        }
+
+        failed
+    }
+
+    /// Add the readings from one cycle, and write them out if the flush
+    /// interval has passed, without running past `deadline`. Returns the
+    /// number of lines that could not be written, counting each line only
+    /// the first time it isn't.
+    pub fn write(&mut self, payload: &Payload, deadline: Instant) -> usize
+    {
+        self.buffer.extend(line_protocol(&self.config.measurement, self.host, payload));
+
+        let limit = self.config.batch_size * MAX_BUFFERED_BATCHES;
+        let mut failed = 0;
+        if self.buffer.len() > limit {
+            let dropped = self.buffer.len() - limit;
+            eprintln!("InfluxDB buffer is full, dropping the {} oldest lines", dropped);
+            failed += self.drop_oldest(dropped);
+        }
+
+        if self.last_flush.elapsed() >= self.config.flush_interval {
+            failed += self.flush(deadline);
+        }
+        failed
+    }
+}
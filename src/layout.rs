@@ -0,0 +1,171 @@
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::payload::Payload;
+use crate::sensors::SensorReport;
+
+
+/// Which topics readings are published to. Any combination can be enabled:
+///
+/// - `aggregate`: one JSON object with every sensor on `<topic>`
+/// - `sensor`: one JSON object per sensor on `<topic>/<sensor_id>`
+/// - `quantity`: a plain number per quantity on `<topic>/<sensor_id>/<quantity>`
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "Vec<String>")]
+pub struct TopicLayout
+{
+    pub aggregate: bool,
+    pub per_sensor: bool,
+    pub per_quantity: bool
+}
+
+impl Default for TopicLayout
+{
+    fn default() -> TopicLayout
+    {
+        TopicLayout { aggregate: true, per_sensor: false, per_quantity: false }
+    }
+}
+
+impl TryFrom<Vec<String>> for TopicLayout
+{
+    type Error = String;
+
+    fn try_from(modes: Vec<String>) -> Result<TopicLayout, String>
+    {
+        let mut layout = TopicLayout { aggregate: false, per_sensor: false, per_quantity: false };
+
+        for mode in modes {
+            match mode.trim() {
+                "aggregate" => layout.aggregate = true,
+                "sensor" => layout.per_sensor = true,
+                "quantity" => layout.per_quantity = true,
+                other => return Err(format!(
+                    "unknown topic layout '{}', expected aggregate, sensor or quantity", other
+                ))
+            }
+        }
+
+        if !(layout.aggregate || layout.per_sensor || layout.per_quantity) {
+            return Err("at least one topic layout must be enabled".into());
+        }
+
+        Ok(layout)
+    }
+}
+
+/// Parses a comma separated list, such as `aggregate,quantity`.
+impl FromStr for TopicLayout
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TopicLayout, String>
+    {
+        TopicLayout::try_from(s.split(',').map(String::from).collect::<Vec<_>>())
+    }
+}
+
+impl fmt::Display for TopicLayout
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        let mut modes = Vec::new();
+        if self.aggregate {
+            modes.push("aggregate");
+        }
+        if self.per_sensor {
+            modes.push("sensor");
+        }
+        if self.per_quantity {
+            modes.push("quantity");
+        }
+        write!(f, "{}", modes.join(","))
+    }
+}
+
+
+/// One message to publish.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message
+{
+    pub topic: String,
+    pub body: String,
+    pub content_type: &'static str,
+    /// What the body holds, sent as user properties over MQTT v5.
+    pub properties: Vec<(String, String)>
+}
+
+impl Message
+{
+    /// A message carrying the firmware version, as every message we publish
+    /// does.
+    pub fn new(topic: String, body: String, content_type: &'static str) -> Message
+    {
+        let properties = vec![("firmware_version".into(), env!("CARGO_PKG_VERSION").into())];
+        Message { topic, body, content_type, properties }
+    }
+
+    fn with_property(mut self, name: &str, value: &str) -> Message
+    {
+        self.properties.push((name.into(), value.into()));
+        self
+    }
+}
+
+pub const JSON: &str = "application/json";
+pub const PLAIN_TEXT: &str = "text/plain";
+
+
+impl TopicLayout
+{
+    /// Topic for a single sensor, below `base`.
+    pub fn sensor_topic(base: &str, id: &str) -> String
+    {
+        format!("{}/{}", base, id)
+    }
+
+    /// Topic for a single quantity of a sensor, below `base`.
+    pub fn quantity_topic(base: &str, id: &str, quantity: &str) -> String
+    {
+        format!("{}/{}/{}", base, id, quantity)
+    }
+
+    /// The topics and message bodies to publish for one read cycle.
+    pub fn messages(&self, base: &str, payload: &Payload) -> serde_json::Result<Vec<Message>>
+    {
+        let mut messages = Vec::new();
+
+        if self.aggregate {
+            messages.push(Message::new(base.to_string(), payload.to_json()?, JSON));
+        }
+
+        for (id, entry) in &payload.sensors {
+            if self.per_sensor {
+                let message = Message::new(
+                    TopicLayout::sensor_topic(base, id),
+                    serde_json::to_string(&payload.sensor_message(entry))?,
+                    JSON
+                ).with_property("sensor_type", entry.report.sensor_type());
+                messages.push(message);
+            }
+
+            // A failed read has no value to publish as a plain number, the
+            // error is still visible on the JSON topics.
+            if let (true, SensorReport::Ok(reading)) = (self.per_quantity, &entry.report) {
+                for quantity in &reading.quantities {
+                    let topic = TopicLayout::quantity_topic(base, id, quantity.name);
+                    let message = Message::new(topic, quantity.value.to_string(), PLAIN_TEXT)
+                        .with_property("sensor_type", reading.sensor_type)
+                        .with_property("unit", quantity.unit);
+                    messages.push(message);
+                }
+            }
+        }
+
+        Ok(messages)
+    }
+}
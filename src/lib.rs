@@ -0,0 +1,16 @@
+
+pub mod sensors;
+pub mod payload;
+pub mod config;
+pub mod discovery;
+pub mod layout;
+pub mod mqtt;
+pub mod shutdown;
+pub mod queue;
+pub mod metrics;
+pub mod influxdb;
+pub mod filelog;
+pub mod history;
+pub mod http;
+pub mod api;
+pub mod hotplug;
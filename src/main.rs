@@ -1,311 +1,417 @@
 
 use std::error::Error;
-use std::thread;
-use std::time::Duration;
-use chrono::{Local};
-use lazy_static::lazy_static;
-use std::ffi::CString;
-use std::env;
-use std::collections::HashMap;
+use std::time::{Duration, Instant};
 use std::path::PathBuf;
-use std::fs;
-use std::ffi::{OsString, OsStr};
-use std::str::FromStr;
-
-use serde::Serialize;
+use std::process;
+
+use chrono::{TimeZone as _, Utc};
+use structopt::StructOpt;
+
+use sensor_reader::api::{self, Api};
+use sensor_reader::config::{Config, InfluxApi, DEFAULT_CONFIG_PATH};
+use sensor_reader::sensors::{get_sensors, SensorList, SensorReport};
+use sensor_reader::payload::{Payload, Timestamp};
+use sensor_reader::filelog::FileLogger;
+use sensor_reader::history::{parse_duration, parse_time, History};
+use sensor_reader::hotplug::{self, EventKind};
+use sensor_reader::influxdb::InfluxWriter;
+use sensor_reader::metrics::{self, Metrics};
+use sensor_reader::mqtt::{self, MqttPublisher};
+use sensor_reader::shutdown::Shutdown;
+
+
+#[derive(StructOpt)]
+#[structopt(about = "Reads the sensors attached to a Raspberry Pi and publishes them over MQTT, \
+    to InfluxDB or to local files")]
+struct Options
+{
+    /// Configuration file, defaults to /etc/sensor_reader.toml if it exists
+    #[structopt(short, long, parse(from_os_str), env = "SENSOR_READER_CONFIG")]
+    config: Option<PathBuf>,
 
+    #[structopt(subcommand)]
+    command: Option<Command>
+}
 
-fn parse<S: FromStr, E>(input: Result<String, E>, default: S) -> S
+#[derive(StructOpt)]
+enum Command
 {
-    let mut result = default;
-    if let Ok(val) = input {
-        if let Ok(update) = S::from_str(&val) {
-            result = update;
-        } else {
-            eprintln!("Error unpacking {}", val);
-        }
-    } else {
-        panic!("Error unpacking");
+    /// Publish readings to the configured sinks until stopped (the default)
+    Run,
+    /// Discover the attached sensors and print their ids, types, aliases and
+    /// locations
+    ListSensors,
+    /// Read every sensor once and print the payload to stdout, without MQTT
+    ReadOnce,
+    /// Check the configuration and report any problems
+    CheckConfig,
+    /// Summarise the stored readings of a sensor over a time range
+    History
+    {
+        /// Sensor id or alias, as printed by list-sensors
+        sensor: String,
+        /// Only show this quantity, such as temperature
+        #[structopt(short, long)]
+        quantity: Option<String>,
+        /// Start of the range, as RFC3339, YYYY-MM-DD or a duration ago such as 24h
+        #[structopt(long, default_value = "24h")]
+        from: String,
+        /// End of the range, in the same forms as --from [default: now]
+        #[structopt(long)]
+        to: Option<String>,
+        /// Length of each bucket, such as 15m, 1h or 1d
+        #[structopt(short, long, default_value = "1h", parse(try_from_str = parse_duration))]
+        bucket: Duration
     }
-    result
 }
 
 
-struct Environment
+fn discover(config: &Config) -> Result<SensorList, Box<dyn Error>>
 {
-    host: String,
-    mqtt_user: String,
-    mqtt_password: String,
-
-    mqtt_host: String,
-    mqtt_port: i32,
-
-    topic: String,
-    qos: i32,
-    interval: f32,
-    ca_cert: PathBuf,
-
-    client_cert: Option<PathBuf>,
-    client_cert_key: Option<PathBuf>,
-    client_cert_key_pass: Option<String>
+    get_sensors(
+        &config.sensors.w1_device_path,
+        config.sensors.am2320_device.as_deref()
+    )
 }
 
-impl Environment
+/// Read every sensor once, applying the retry policy, and gather the results
+/// into a payload.
+fn read_sensors(config: &Config, sensors: &SensorList) -> Payload
 {
-    fn new() -> Environment
-    {
-        let host = env::var("HOSTNAME")
-            .expect("Could not read HOSTNAME");
-        let mqtt_user = env::var("MQTT_USER")
-            .expect("Could not read MQTT_USER");
-        let mqtt_password = env::var("MQTT_PASSWORD")
-            .expect("MQTT_PASSWORD");
-        let mqtt_host = env::var("MQTT_HOST")
-            .expect("MQTT_HOST");
-        let mqtt_port = parse(env::var("MQTT_PORT"), 8883i32);
-        let topic = env::var("MQTT_TOPIC")
-            .expect("MQTT_TOPIC");
-        let qos = parse(env::var("MQTT_QOS"), 1i32);
-        let interval = parse(env::var("MQTT_READ_INTERVAL"), 10.0f32);
-        let ca_cert = env::var("CA_CERT").map(PathBuf::from)
-            .expect("Could not read CA_CERT");
-        let client_cert = env::var("CLIENT_CERT").map(PathBuf::from)
-            .ok();
-        let client_cert_key = env::var("CLIENT_CERT_KEY").map(PathBuf::from)
-            .ok();
-        let client_cert_key_pass = env::var("CLIENT_CERT_KEY_PASS")
-            .ok();
-
-        Environment {
-            host,
-            mqtt_user,
-            mqtt_password,
-            mqtt_host,
-            mqtt_port,
-            topic,
-            qos,
-            interval,
-            ca_cert,
-            client_cert,
-            client_cert_key,
-            client_cert_key_pass
+    let mut payload = Payload::new(Timestamp::now(config.timestamps.zone));
+    let deadline = Instant::now() + config.read.deadline;
+
+    for sensor in sensors {
+        let retry_policy = config.retry_policy(sensor.identifier());
+        let result = retry_policy.read(sensor.as_ref(), deadline);
+        if let Err(ref e) = result {
+            eprintln!("Error reading sensor {}: {}", sensor.identifier(), e);
+        }
+        let time = if config.timestamps.per_sensor {
+            Some(Timestamp::now(config.timestamps.zone))
+        } else {
+            None
+        };
+        let id = sensor.identifier();
+        let report = SensorReport::new(sensor.sensor_type(), result);
+        match config.sensors.metadata(id).alias {
+            Some(ref alias) => payload.insert_alias(alias, id, report, time),
+            None => payload.insert(id, report, time)
         }
     }
-}
 
-lazy_static! {
-    static ref ENVIRONMENT: Environment = Environment::new();
-    static ref DS18B20_DEVICE_PATH: PathBuf = PathBuf::from("/sys/bus/w1/devices/");
+    payload
 }
 
-fn get_client() -> Result<paho_mqtt::Client, Box<dyn Error>>
+fn run(config: &Config) -> Result<(), Box<dyn Error>>
 {
-    eprintln!("Setting up client options");
-    let options = paho_mqtt::CreateOptionsBuilder::new()
-        .server_uri(format!("ssl://{}:{}", &ENVIRONMENT.mqtt_host, &ENVIRONMENT.mqtt_port))
-        .client_id(&ENVIRONMENT.host)
-        .finalize();
-
-    eprintln!("Creating client");
-    let client = paho_mqtt::Client::new(options)?;
-
-    eprintln!("Setting up SSL options");
-    let mut ssl_options_builder = paho_mqtt::SslOptionsBuilder::new();
-
-    ssl_options_builder
-        .trust_store(&ENVIRONMENT.ca_cert)?;
-
-    if let Some(ref client_cert) = &ENVIRONMENT.client_cert {
-        if let Some(ref client_key) = &ENVIRONMENT.client_cert_key {
-            if let Some(ref client_key_pass) = &ENVIRONMENT.client_cert_key_pass {
-                ssl_options_builder
-                    .key_store(client_cert)?
-                    .private_key(&client_key)?
-                    .private_key_password(client_key_pass);
-            }
-        }
+    if config.sinks.is_empty() && config.history.is_none() {
+        return Err("nowhere to send readings, configure MQTT (sinks.mqtt / MQTT_HOST), \
+            InfluxDB (sinks.influxdb / INFLUXDB_URL), a log file (sinks.file / FILE_LOG_DIR) \
+            or the history (history.path / HISTORY_DB)".into());
     }
 
-    let ssl_options = ssl_options_builder.finalize();
+    let shutdown = Shutdown::register()?;
 
-    eprintln!("Creating connect options");
+    let metrics = match config.metrics {
+        Some(ref metrics_config) => {
+            let metrics = Metrics::new(&config.host);
+            metrics::serve(metrics_config.listen, metrics.clone())?;
+            eprintln!("Serving metrics on http://{}/metrics", metrics_config.listen);
+            Some(metrics)
+        },
+        None => None
+    };
+
+    let api = match config.api {
+        Some(ref api_config) => {
+            let api = Api::new(&config.host);
+            api::serve(api_config.listen, api.clone())?;
+            eprintln!("Serving the API on http://{}/", api_config.listen);
+            Some(api)
+        },
+        None => None
+    };
+
+    let mut history = match config.history {
+        Some(ref history) => Some(History::open(&history.path)?),
+        None => None
+    };
+    let mut file_log = match config.sinks.file {
+        Some(ref file) => Some(FileLogger::open(file)?),
+        None => None
+    };
+    let mut publisher = match config.sinks.mqtt {
+        Some(ref mqtt) => Some(MqttPublisher::connect(mqtt, &config.host)?),
+        None => None
+    };
+    let mut influxdb = config.sinks.influxdb.as_ref()
+        .map(|influxdb| InfluxWriter::new(influxdb, &config.host));
+
+    // Otherwise /health says the broker is not configured until the first
+    // cycle is over
+    if let (Some(api), Some(publisher)) = (&api, &publisher) {
+        api.set_broker_connected(publisher.is_connected());
+    }
 
-    let connect_options = paho_mqtt::ConnectOptionsBuilder::new()
-        .user_name(&ENVIRONMENT.mqtt_user)
-        .password(&ENVIRONMENT.mqtt_password)
-        .ssl_options(ssl_options)
-        .automatic_reconnect(Duration::from_secs(1), Duration::from_secs(10))
-        .retry_interval(Duration::from_secs(5))
-        .finalize();
+    let wait_time = config.read.interval;
 
-    eprintln!("Connecting");
+    let mut sensors = match discover(config) {
+        Ok(sensors) => sensors,
+        Err(e) => {
+            if let Some(publisher) = publisher {
+                publisher.shutdown();
+            }
+            return Err(e);
+        }
+    };
 
-    client.connect(
-        connect_options
-    ).unwrap();
+    if let Some(ref api) = api {
+        api.set_sensors(&sensors, &config.sensors);
+    }
 
-    eprintln!("Connected");
+    // Otherwise this happens once the publisher has reconnected
+    if let (Some(publisher), Some(mqtt)) = (&publisher, &config.sinks.mqtt) {
+        if publisher.is_connected() {
+            mqtt::announce_sensors(publisher.client(), config, mqtt, &sensors);
+        }
+    }
 
-    Ok(client)
-}
+    let mut last_scan = Instant::now();
+
+    while shutdown.sleep(wait_time) {
+        let cycle_start = Instant::now();
+        let mut failures = Vec::new();
+
+        // Pick up probes that have been plugged in or swapped, and stop
+        // reading ones that have gone
+        let rescan_due = config.read.rescan_interval
+            .is_some_and(|interval| last_scan.elapsed() >= interval);
+        if rescan_due {
+            last_scan = Instant::now();
+            let events = match discover(config) {
+                Ok(found) => hotplug::rescan(&mut sensors, found, &Timestamp::now(config.timestamps.zone)),
+                Err(e) => {
+                    eprintln!("An error occurred looking for sensors: {}", e);
+                    Vec::new()
+                }
+            };
+
+            for event in &events {
+                eprintln!("Sensor {} {} ({})", event.id, event.event, event.sensor_type);
+
+                if let Some(ref mut publisher) = publisher {
+                    failures.push(("mqtt", publisher.publish_event(event)));
+                }
+
+                match event.event {
+                    EventKind::Added => {
+                        let sensor = sensors.iter().find(|s| s.identifier() == event.id);
+                        if let (Some(publisher), Some(mqtt), Some(sensor)) = (&publisher, &config.sinks.mqtt, sensor) {
+                            mqtt::announce_sensor(publisher.client(), config, mqtt, sensor.as_ref());
+                        }
+                    },
+                    EventKind::Removed => {
+                        if let (Some(publisher), Some(mqtt)) = (&publisher, &config.sinks.mqtt) {
+                            mqtt::retract_sensor(publisher.client(), config, mqtt, &event.id);
+                        }
+                        if let Some(ref metrics) = metrics {
+                            metrics.remove_sensor(&event.id);
+                        }
+                    }
+                }
+            }
 
-trait Sensor
-{
-    type ReadingType: Serialize;
+            if let Some(ref api) = api {
+                if !events.is_empty() {
+                    api.set_sensors(&sensors, &config.sensors);
+                }
+            }
+        }
+
+        let payload = read_sensors(config, &sensors);
 
-    fn identifier(&self) -> &str;
-    fn read(&self) -> Self::ReadingType;
+        if let Some(ref mut publisher) = publisher {
+            failures.push(("mqtt", publisher.publish(&payload)));
+            // Retained metadata may have been missed while disconnected
+            if publisher.reconnected() {
+                if let Some(ref mqtt) = config.sinks.mqtt {
+                    mqtt::announce_sensors(publisher.client(), config, mqtt, &sensors);
+                }
+            }
+        }
+        if let Some(ref mut influxdb) = influxdb {
+            // Retries while InfluxDB is down mustn't hold up the next cycle
+            failures.push(("influxdb", influxdb.write(&payload, cycle_start + config.read.interval)));
+        }
+        if let Some(ref mut file_log) = file_log {
+            if let Err(e) = file_log.write(&payload) {
+                eprintln!("An error occurred writing the reading log {:?}", e);
+                failures.push(("file", 1));
+            }
+        }
+        if let Some(ref mut history) = history {
+            if let Err(e) = history.record(&payload) {
+                eprintln!("An error occurred storing readings in the history {:?}", e);
+                failures.push(("history", 1));
+            }
+        }
 
-    fn read_to_string(&self) -> String;
+        if let Some(ref metrics) = metrics {
+            metrics.record(&payload);
+            for (sink, failed) in failures {
+                metrics.publish_failed(sink, failed);
+            }
+        }
+        if let Some(ref api) = api {
+            api.record(&payload);
+            if let Some(ref publisher) = publisher {
+                api.set_broker_connected(publisher.is_connected());
+            }
+        }
+    }
+
+    eprintln!("Shutting down");
+    if let Some(ref mut influxdb) = influxdb {
+        influxdb.flush(Instant::now() + config.read.interval);
+    }
+    if let Some(publisher) = publisher {
+        publisher.shutdown();
+    }
+
+    Ok(())
 }
 
-struct DS18B20Sensor
+fn list_sensors(config: &Config) -> Result<(), Box<dyn Error>>
 {
-    id: String
+    for sensor in discover(config)? {
+        let id = sensor.identifier();
+        let metadata = config.sensors.metadata(id);
+        println!("{}\t{}\t{}\t{}", id, sensor.sensor_type(),
+            metadata.alias.as_deref().unwrap_or("-"), metadata.location.as_deref().unwrap_or("-"));
+    }
+    Ok(())
 }
 
-#[derive(Serialize)]
-struct DS18B20Reading
+fn read_once(config: &Config) -> Result<(), Box<dyn Error>>
 {
-    temperature: f32
+    let sensors = discover(config)?;
+    let payload = read_sensors(config, &sensors);
+    println!("{}", serde_json::to_string_pretty(&payload)?);
+    Ok(())
 }
 
-impl DS18B20Reading
+fn check_config(config: &Config) -> Result<(), Box<dyn Error>>
 {
-    fn new(temperature: f32) -> DS18B20Reading
-    {
-        DS18B20Reading { temperature }
+    println!("Configuration OK");
+    println!("  host: {}", config.host);
+    println!("  read interval: {:?}", config.read.interval);
+    match config.read.rescan_interval {
+        Some(interval) => println!("  rescan interval: {:?}", interval),
+        None => println!("  rescan interval: only at startup")
     }
-}
-
-impl DS18B20Sensor {
-    fn new(id_os: &str) -> DS18B20Sensor
-    {
-        DS18B20Sensor { id: id_os.into() }
+    println!("  1-Wire devices: {}", config.sensors.w1_device_path.display());
+    if let Some(ref device) = config.sensors.am2320_device {
+        println!("  AM2320: {}", device.display());
     }
-}
-
-impl Sensor for DS18B20Sensor {
-
-    type ReadingType = DS18B20Reading;
-
-    fn identifier(&self) -> &str
-    {
-         &self.id
+    match config.sinks.mqtt {
+        Some(ref mqtt) => println!(
+            "  MQTT {}: {} topic {} ({})",
+            mqtt.version, mqtt::server_uri(mqtt), mqtt.topic, mqtt.topic_layout
+        ),
+        None => println!("  MQTT: not configured")
     }
-
-    fn read(&self) -> Self::ReadingType
-    {
-        let path = DS18B20_DEVICE_PATH.join(&self.id).join("w1_slave");
-
-        let string_contents: String = match fs::read(&path) {
-        Ok(contents) => {
-        String::from_utf8(contents).unwrap_or("".into())
-        },
-        Err(_) => {
-        return DS18B20Reading::new(f32::NAN);
-        }
-        };
-
-        // This is a really naive implementation, needs more robustness
-        if string_contents.is_empty() {
-        return DS18B20Reading::new(f32::NAN);
-        }
-
-        let mut lines = string_contents.lines();
-
-        let line1 = match lines.next() {
-        Some(line) => line,
-        None => return DS18B20Reading::new(f32::NAN)
+    if let Some(ref influxdb) = config.sinks.influxdb {
+        let target = match influxdb.api {
+            InfluxApi::V1 { ref database, .. } => format!("database {}", database),
+            InfluxApi::V2 { ref org, ref bucket, .. } => format!("bucket {}/{}", org, bucket)
         };
-
-        let line2 = match lines.next() {
-        Some(line) => line,
-        None => return DS18B20Reading::new(f32::NAN)
-        };
-
-        if !line1.ends_with("YES") {
-        return DS18B20Reading::new(f32::NAN)
-        }
-
-        let itemp: i32 = match line2.rsplit('=').next().map(i32::from_str) {
-        Some(Ok(v)) => v,
-        _ => return DS18B20Reading::new(f32::NAN)
-        };
-
-        DS18B20Reading::new((itemp as f32) / 1000.0f32)
+        println!("  InfluxDB: {} {} (measurement {})", influxdb.url, target, influxdb.measurement);
     }
-
-    fn read_to_string(&self) -> String
-    {
-        let reading = self.read();
-        serde_json::to_string(&reading).unwrap()
+    if let Some(ref file) = config.sinks.file {
+        println!("  log files: {} ({})", file.dir.display(), file.format);
     }
-}
-
-fn get_sensors() -> Result<Vec<Box<dyn Sensor<ReadingType = DS18B20Reading>>>, Box<dyn Error> >
-{
-    let mut result: Vec<Box<dyn Sensor<ReadingType = _>>> = Vec::new();
-
-    for device in fs::read_dir(&DS18B20_DEVICE_PATH.as_path())? {
-        if let Ok(dev) = device {
-            let path = dev.path();
-            let id = path
-                .strip_prefix(DS18B20_DEVICE_PATH.as_path())
-                .unwrap().as_os_str().to_string_lossy();
-            if !id.starts_with("28-") {
-                 continue;
-            }
-
-            result.push(Box::new(DS18B20Sensor::new(&id)));
-        }
+    if let Some(ref history) = config.history {
+        println!("  history: {}", history.path.display());
     }
-
-    // Get the AM2320 sensor, which is a bit more complicated.
-
-    Ok(result)
+    if let Some(ref api) = config.api {
+        println!("  API: http://{}/", api.listen);
+    }
+    if let Some(ref metrics) = config.metrics {
+        println!("  metrics: http://{}/metrics", metrics.listen);
+    }
+    Ok(())
 }
 
-
-
-fn main() -> Result<(), Box<dyn Error>>
+fn history(config: &Config, sensor: &str, quantity: Option<&str>,
+           from: &str, to: Option<&str>, bucket: Duration)
+    -> Result<(), Box<dyn Error>>
 {
-    let client = get_client()?;
-
-    let wait_time = Duration::from_secs_f32(ENVIRONMENT.interval);
-
-    let sensors = get_sensors()?;
-
-    loop {
-        thread::sleep(wait_time);
-
-        let mut readings: HashMap<&str, Box<DS18B20Reading>> = HashMap::new();
-
-        for sensor in &sensors {
-            readings.insert(sensor.identifier(), Box::new(sensor.read()));
-        }
-
-        let message = paho_mqtt::Message::new(
-            &ENVIRONMENT.topic,
-            serde_json::to_string(&readings).unwrap_or("ERR".into()),
-            ENVIRONMENT.qos
-        );
+    let path = &config.history.as_ref()
+        .ok_or("no history database configured (history.path / HISTORY_DB)")?
+        .path;
+    if !path.exists() {
+        return Err(format!("{} does not exist, nothing has been recorded yet", path.display()).into());
+    }
 
-        if !client.is_connected() {
-            if let Err(_e) = client.reconnect() {
-                continue;
-            }
-        }
+    let zone = config.timestamps.zone;
+    let now = Utc::now();
+    let from = parse_time(from, zone, now)?;
+    let to = match to {
+        Some(to) => parse_time(to, zone, now)?,
+        None => now
+    };
+
+    // Readings are stored under the sensor's id, whatever its alias was
+    let sensor = config.sensors.id(sensor);
+    let buckets = History::open(path)?.query(sensor, quantity, from, to, bucket)?;
+    if buckets.is_empty() {
+        println!("No readings of {} between {} and {}",
+            sensor, Timestamp::new(from, zone).timestamp, Timestamp::new(to, zone).timestamp);
+        return Ok(());
+    }
 
-        if let Err(e) = client.publish(message) {
-            eprintln!("An error occurred publishing message {:?}", e);
+    let mut current = None;
+    for b in &buckets {
+        if current != Some(&b.quantity) {
+            println!("{}{} ({})", if current.is_some() { "\n" } else { "" }, b.quantity, b.unit);
+            println!("{:<29} {:>6} {:>9} {:>9} {:>9}", "start", "count", "min", "max", "mean");
+            current = Some(&b.quantity);
         }
-
+        let start = Utc.timestamp_millis(b.start_ms);
+        println!("{:<29} {:>6} {:>9.2} {:>9.2} {:>9.2}",
+            Timestamp::new(start, zone).timestamp, b.count, b.min, b.max, b.mean);
     }
 
     Ok(())
 }
 
-
-
-
+fn main()
+{
+    let options = Options::from_args();
+
+    // An explicitly requested configuration file has to exist
+    let (config_path, required) = match options.config {
+        Some(path) => (path, true),
+        None => (PathBuf::from(DEFAULT_CONFIG_PATH), false)
+    };
+    let config = Config::load(&config_path, required).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2)
+    });
+
+    let result = match options.command.unwrap_or(Command::Run) {
+        Command::Run => run(&config),
+        Command::ListSensors => list_sensors(&config),
+        Command::ReadOnce => read_once(&config),
+        Command::CheckConfig => check_config(&config),
+        Command::History { sensor, quantity, from, to, bucket } => history(
+            &config, &sensor, quantity.as_deref(), &from, to.as_deref(), bucket
+        )
+    };
+
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
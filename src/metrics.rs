@@ -0,0 +1,191 @@
+
+//! Prometheus exporter. The read loop records each cycle here, and a small
+//! HTTP server renders the latest state on `/metrics` in the text exposition
+//! format.
+//!
+//! See https://prometheus.io/docs/instrumenting/exposition_formats/
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use crate::http;
+use crate::payload::Payload;
+use crate::sensors::{Quantity, SensorReport};
+
+
+/// Metric name for a quantity, with its unit as the suffix, for example
+/// `sensor_reader_temperature_celsius`.
+fn quantity_metric(quantity: &Quantity) -> String
+{
+    let unit = match quantity.unit {
+        "°C" => "_celsius",
+        "%" => "_percent",
+        _ => ""
+    };
+    format!("sensor_reader_{}{}", quantity.name, unit)
+}
+
+fn escape_label(value: &str) -> String
+{
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Renders label pairs as `{name="value",...}`.
+fn labels(pairs: &[(&str, &str)]) -> String
+{
+    let pairs: Vec<String> = pairs.iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape_label(value)))
+        .collect();
+    format!("{{{}}}", pairs.join(","))
+}
+
+/// A sensor's id, alias and type.
+type SensorLabels = (String, Option<String>, &'static str);
+
+/// The labels that say which sensor a sample is from. The `sensor` label is
+/// always the id, so renaming an alias doesn't split the series.
+fn sensor_labels<'a>(sensor: &'a SensorLabels, host: &'a str) -> Vec<(&'static str, &'a str)>
+{
+    let (id, alias, sensor_type) = sensor;
+    let mut pairs = vec![("sensor", id.as_str())];
+    if let Some(alias) = alias {
+        pairs.push(("alias", alias.as_str()));
+    }
+    pairs.push(("type", *sensor_type));
+    pairs.push(("host", host));
+    pairs
+}
+
+
+#[derive(Default)]
+struct State
+{
+    /// Latest value of each quantity, by metric name, then sensor. A sensor
+    /// that fails to read is dropped, rather than left reporting its last
+    /// good value.
+    values: BTreeMap<String, BTreeMap<SensorLabels, f32>>,
+    /// Failed reads by sensor and error kind.
+    read_errors: BTreeMap<(SensorLabels, &'static str), u64>,
+    /// Messages that could not be published, by sink.
+    publish_failures: BTreeMap<&'static str, u64>
+}
+
+
+/// The metrics for this node, shared between the read loop and the server.
+#[derive(Clone)]
+pub struct Metrics
+{
+    host: String,
+    state: Arc<Mutex<State>>
+}
+
+impl Metrics
+{
+    pub fn new(host: &str) -> Metrics
+    {
+        Metrics { host: host.into(), state: Arc::new(Mutex::new(State::default())) }
+    }
+
+    /// Update the gauges and error counters from one read cycle.
+    pub fn record(&self, payload: &Payload)
+    {
+        let mut state = self.state.lock().unwrap();
+
+        for (key, entry) in &payload.sensors {
+            let id = entry.sensor_id(key);
+            let sensor_type = entry.report.sensor_type();
+            for values in state.values.values_mut() {
+                values.retain(|(sensor, _, kind), _| sensor != id || *kind != sensor_type);
+            }
+
+            let sensor = (id.to_string(), entry.alias(key).map(String::from), sensor_type);
+            match entry.report {
+                SensorReport::Ok(ref reading) => {
+                    for quantity in &reading.quantities {
+                        state.values.entry(quantity_metric(quantity))
+                            .or_default()
+                            .insert(sensor.clone(), quantity.value);
+                    }
+                },
+                SensorReport::Error { error, .. } => {
+                    *state.read_errors.entry((sensor, error)).or_insert(0) += 1;
+                }
+            }
+        }
+
+        state.values.retain(|_, values| !values.is_empty());
+    }
+
+    /// Drop the gauges of a sensor that has gone away. Its error counters
+    /// are kept.
+    pub fn remove_sensor(&self, id: &str)
+    {
+        let mut state = self.state.lock().unwrap();
+        for values in state.values.values_mut() {
+            values.retain(|(sensor, _, _), _| sensor != id);
+        }
+        state.values.retain(|_, values| !values.is_empty());
+    }
+
+    /// Count messages that `sink` failed to publish.
+    pub fn publish_failed(&self, sink: &'static str, count: usize)
+    {
+        if count > 0 {
+            let mut state = self.state.lock().unwrap();
+            *state.publish_failures.entry(sink).or_insert(0) += count as u64;
+        }
+    }
+
+    /// The current state in the Prometheus text format.
+    pub fn render(&self) -> String
+    {
+        let state = self.state.lock().unwrap();
+        let host = self.host.as_str();
+        let mut out = String::new();
+
+        for (metric, values) in &state.values {
+            writeln!(out, "# HELP {} Latest reading of each sensor.", metric).unwrap();
+            writeln!(out, "# TYPE {} gauge", metric).unwrap();
+            for (sensor, value) in values {
+                writeln!(out, "{}{} {}", metric, labels(&sensor_labels(sensor, host)), value).unwrap();
+            }
+        }
+
+        let metric = "sensor_reader_read_errors_total";
+        writeln!(out, "# HELP {} Sensor reads that failed, after retries.", metric).unwrap();
+        writeln!(out, "# TYPE {} counter", metric).unwrap();
+        for ((sensor, error), count) in &state.read_errors {
+            let mut pairs = sensor_labels(sensor, host);
+            pairs.push(("error", *error));
+            writeln!(out, "{}{} {}", metric, labels(&pairs), count).unwrap();
+        }
+
+        let metric = "sensor_reader_publish_failures_total";
+        writeln!(out, "# HELP {} Messages that could not be published.", metric).unwrap();
+        writeln!(out, "# TYPE {} counter", metric).unwrap();
+        for (sink, count) in &state.publish_failures {
+            let labels = labels(&[("sink", *sink), ("host", host)]);
+            writeln!(out, "{}{} {}", metric, labels, count).unwrap();
+        }
+
+        out
+    }
+}
+
+
+/// Serve `/metrics` on `listen` from a background thread, for as long as the
+/// process runs.
+pub fn serve(listen: SocketAddr, metrics: Metrics) -> Result<(), Box<dyn Error>>
+{
+    http::serve(listen, move |_, path| {
+        if path == "/metrics" {
+            tiny_http::Response::from_string(metrics.render())
+                .with_header(http::header("Content-Type", "text/plain; version=0.0.4"))
+        } else {
+            tiny_http::Response::from_string("Not Found").with_status_code(404)
+        }
+    })
+}
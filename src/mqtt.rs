@@ -0,0 +1,622 @@
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::Utc;
+use serde::Deserialize;
+
+use crate::config::{Config, MqttConfig};
+use crate::discovery::Discovery;
+use crate::hotplug::SensorEvent;
+use crate::layout::{Message, JSON};
+use crate::payload::Payload;
+use crate::queue::{DiskQueue, QueuedMessage};
+use crate::sensors::{Sensor, SensorInfo, SensorList};
+
+
+/// Retained on the status topic while we are connected.
+pub const ONLINE: &str = "online";
+/// Retained on the status topic when we disconnect, either by us or by the
+/// broker on our behalf through the last will.
+pub const OFFLINE: &str = "offline";
+
+
+/// How we talk to the broker.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport
+{
+    /// Plain MQTT over TCP.
+    Tcp,
+    /// MQTT over TLS.
+    #[default]
+    Ssl,
+    /// MQTT over WebSockets.
+    Ws,
+    /// MQTT over WebSockets over TLS.
+    Wss
+}
+
+impl Transport
+{
+    pub fn scheme(&self) -> &'static str
+    {
+        match self {
+            Transport::Tcp => "tcp",
+            Transport::Ssl => "ssl",
+            Transport::Ws => "ws",
+            Transport::Wss => "wss"
+        }
+    }
+
+    pub fn default_port(&self) -> i32
+    {
+        match self {
+            Transport::Tcp => 1883,
+            Transport::Ssl => 8883,
+            Transport::Ws => 80,
+            Transport::Wss => 443
+        }
+    }
+
+    pub fn uses_tls(&self) -> bool
+    {
+        matches!(self, Transport::Ssl | Transport::Wss)
+    }
+
+    pub fn is_websocket(&self) -> bool
+    {
+        matches!(self, Transport::Ws | Transport::Wss)
+    }
+}
+
+impl FromStr for Transport
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Transport, String>
+    {
+        match s.to_lowercase().as_str() {
+            "tcp" => Ok(Transport::Tcp),
+            "ssl" | "tls" => Ok(Transport::Ssl),
+            "ws" => Ok(Transport::Ws),
+            "wss" => Ok(Transport::Wss),
+            _ => Err(format!("unknown transport '{}', expected tcp, ssl, ws or wss", s))
+        }
+    }
+}
+
+impl fmt::Display for Transport
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "{}", self.scheme())
+    }
+}
+
+/// Which version of the protocol to speak.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProtocolVersion
+{
+    #[default]
+    #[serde(rename = "3.1.1")]
+    V3_1_1,
+    /// Needed for message properties such as the content type and expiry.
+    #[serde(rename = "5")]
+    V5
+}
+
+impl ProtocolVersion
+{
+    fn paho_version(&self) -> u32
+    {
+        match self {
+            ProtocolVersion::V3_1_1 => paho_mqtt::MQTT_VERSION_3_1_1,
+            ProtocolVersion::V5 => paho_mqtt::MQTT_VERSION_5
+        }
+    }
+}
+
+impl FromStr for ProtocolVersion
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ProtocolVersion, String>
+    {
+        match s {
+            "3.1.1" | "3" => Ok(ProtocolVersion::V3_1_1),
+            "5" | "5.0" => Ok(ProtocolVersion::V5),
+            _ => Err(format!("unknown MQTT version '{}', expected 3.1.1 or 5", s))
+        }
+    }
+}
+
+impl fmt::Display for ProtocolVersion
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self {
+            ProtocolVersion::V3_1_1 => write!(f, "3.1.1"),
+            ProtocolVersion::V5 => write!(f, "5")
+        }
+    }
+}
+
+
+/// The URI paho connects to, for example `ssl://broker:8883` or
+/// `wss://proxy:443/mqtt`.
+pub fn server_uri(config: &MqttConfig) -> String
+{
+    let mut uri = format!("{}://{}:{}", config.transport.scheme(), config.host, config.port);
+    if config.transport.is_websocket() {
+        if !config.ws_path.starts_with('/') {
+            uri.push('/');
+        }
+        uri.push_str(&config.ws_path);
+    }
+    uri
+}
+
+
+/// Topic that carries the availability of this node.
+pub fn status_topic(base: &str) -> String
+{
+    format!("{}/status", base)
+}
+
+/// Topic that carries sensors being added and removed.
+pub fn events_topic(base: &str) -> String
+{
+    format!("{}/events", base)
+}
+
+/// Topic that carries the retained description of one sensor, by its alias
+/// or id.
+pub fn metadata_topic(base: &str, key: &str) -> String
+{
+    format!("{}/{}/metadata", base, key)
+}
+
+pub fn publish_status(client: &paho_mqtt::Client, config: &MqttConfig, status: &str)
+    -> paho_mqtt::Result<()>
+{
+    client.publish(paho_mqtt::Message::new_retained(status_topic(&config.topic), status, config.qos))
+}
+
+
+/// How long to wait for in-flight messages to be acknowledged when
+/// disconnecting.
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Announce that we are going away and disconnect cleanly. A clean
+/// disconnect means the broker won't publish our will, so we publish
+/// "offline" ourselves.
+pub fn shutdown(client: &paho_mqtt::Client, config: &MqttConfig)
+{
+    if client.is_connected() {
+        if let Err(e) = publish_status(client, config, OFFLINE) {
+            eprintln!("An error occurred publishing status {:?}", e);
+        }
+    }
+
+    // Gives outstanding QoS 1 and 2 deliveries a chance to complete
+    let options = paho_mqtt::DisconnectOptionsBuilder::new()
+        .timeout(DISCONNECT_TIMEOUT)
+        .finalize();
+
+    if let Err(e) = client.disconnect(options) {
+        eprintln!("An error occurred disconnecting {:?}", e);
+    }
+}
+
+
+/// Keeps the status topic saying "online" while we are connected. The
+/// broker will have published our will if the connection dropped, so we need
+/// to announce ourselves again each time it comes back.
+pub struct Availability
+{
+    announced: bool
+}
+
+impl Availability
+{
+    pub fn new() -> Availability
+    {
+        Availability { announced: false }
+    }
+
+    /// Record that the connection has been, or may have been, lost.
+    pub fn lost(&mut self)
+    {
+        self.announced = false;
+    }
+
+    /// Publish "online" if we haven't since the last time the connection was
+    /// lost.
+    pub fn announce(&mut self, client: &paho_mqtt::Client, config: &MqttConfig)
+    {
+        if client.is_connected() {
+            self.announce_with(|| publish_status(client, config, ONLINE));
+        }
+    }
+
+    /// Call `publish` to announce ourselves, unless it has already succeeded
+    /// since the last time the connection was lost.
+    pub fn announce_with<F>(&mut self, publish: F)
+        where F: FnOnce() -> paho_mqtt::Result<()>
+    {
+        if self.announced {
+            return;
+        }
+
+        match publish() {
+            Ok(()) => self.announced = true,
+            Err(e) => eprintln!("An error occurred publishing status {:?}", e)
+        }
+    }
+}
+
+impl Default for Availability
+{
+    fn default() -> Availability
+    {
+        Availability::new()
+    }
+}
+
+
+/// How long to wait for the broker to accept a connection. Reconnecting
+/// happens in the read loop, so this bounds how long a cycle can stall.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The broker publishes this for us if we drop off without saying goodbye.
+pub fn will(config: &MqttConfig) -> paho_mqtt::Message
+{
+    paho_mqtt::Message::new_retained(status_topic(&config.topic), OFFLINE, config.qos)
+}
+
+pub fn get_client(config: &MqttConfig, client_id: &str) -> Result<paho_mqtt::Client, Box<dyn Error>>
+{
+    eprintln!("Setting up client options");
+    let options = paho_mqtt::CreateOptionsBuilder::new()
+        .server_uri(server_uri(config))
+        .client_id(client_id)
+        .mqtt_version(config.version.paho_version())
+        .finalize();
+
+    eprintln!("Creating client");
+    let client = paho_mqtt::Client::new(options)?;
+
+    eprintln!("Creating connect options");
+
+    let mut connect_options_builder = paho_mqtt::ConnectOptionsBuilder::new();
+    // No automatic reconnect: the publisher reconnects itself, so that it
+    // knows to announce itself again after the broker published our will
+    connect_options_builder
+        .mqtt_version(config.version.paho_version())
+        .will_message(will(config))
+        .connect_timeout(CONNECT_TIMEOUT)
+        .retry_interval(Duration::from_secs(5));
+
+    // With client certificates the broker may not want credentials at all
+    if let Some(ref user) = config.user {
+        connect_options_builder.user_name(user);
+    }
+    if let Some(ref password) = config.password {
+        connect_options_builder.password(password);
+    }
+
+    if config.transport.uses_tls() {
+        eprintln!("Setting up SSL options");
+        let mut ssl_options_builder = paho_mqtt::SslOptionsBuilder::new();
+
+        // Without a CA certificate the system trust store is used
+        if let Some(ref ca_cert) = config.ca_cert {
+            ssl_options_builder
+                .trust_store(ca_cert)?;
+        }
+
+        if let Some(ref client_cert) = config.client_cert {
+            ssl_options_builder
+                .key_store(&client_cert.cert)?
+                .private_key(&client_cert.key)?;
+
+            if let Some(ref key_pass) = client_cert.key_pass {
+                ssl_options_builder.private_key_password(key_pass);
+            }
+        }
+
+        connect_options_builder.ssl_options(ssl_options_builder.finalize());
+    }
+
+    let connect_options = connect_options_builder.finalize();
+
+    eprintln!("Connecting");
+
+    // Without a broker we carry on disconnected. The publisher keeps trying
+    // to reconnect, queueing readings in the meantime.
+    match client.connect(connect_options) {
+        Ok(_) => eprintln!("Connected"),
+        Err(e) => eprintln!("Could not connect to {}, will keep trying: {}", server_uri(config), e)
+    }
+
+    Ok(client)
+}
+
+/// Publish the retained metadata of a sensor, and its Home Assistant
+/// discovery messages if discovery is enabled.
+pub fn announce_sensor(client: &paho_mqtt::Client, config: &Config, mqtt: &MqttConfig, sensor: &dyn Sensor)
+{
+    let id = sensor.identifier();
+    let metadata = config.sensors.metadata(id);
+    let mut messages = vec![(
+        metadata_topic(&mqtt.topic, metadata.key(id)),
+        serde_json::to_string(&SensorInfo::new(sensor, metadata)).unwrap()
+    )];
+
+    if mqtt.discovery {
+        let discovery = Discovery::new(&mqtt.discovery_prefix, &config.host, &mqtt.topic)
+            .with_layout(mqtt.topic_layout);
+        messages.extend(discovery.messages(sensor, metadata));
+    }
+
+    for (topic, payload) in messages {
+        let message = paho_mqtt::Message::new_retained(topic, payload, mqtt.qos);
+        if let Err(e) = client.publish(message) {
+            eprintln!("An error occurred publishing metadata for {}: {:?}", id, e);
+        }
+    }
+}
+
+/// Announce every sensor.
+pub fn announce_sensors(client: &paho_mqtt::Client, config: &Config, mqtt: &MqttConfig, sensors: &SensorList)
+{
+    for sensor in sensors {
+        announce_sensor(client, config, mqtt, sensor.as_ref());
+    }
+}
+
+/// Clear the retained metadata of a sensor that has gone away. Its discovery
+/// messages are left, so that Home Assistant keeps its history.
+pub fn retract_sensor(client: &paho_mqtt::Client, config: &Config, mqtt: &MqttConfig, id: &str)
+{
+    let topic = metadata_topic(&mqtt.topic, config.sensors.key(id));
+    let message = paho_mqtt::Message::new_retained(topic, Vec::<u8>::new(), mqtt.qos);
+    if let Err(e) = client.publish(message) {
+        eprintln!("An error occurred clearing metadata for {}: {:?}", id, e);
+    }
+}
+
+
+/// How many seconds a message taken at `timestamp_ms` has left before it
+/// expires, or None if it already has.
+pub fn expiry_left(expiry: Duration, timestamp_ms: i64, now_ms: i64) -> Option<u32>
+{
+    let left_ms = expiry.as_millis() as i64 - (now_ms - timestamp_ms).max(0);
+    if left_ms <= 0 {
+        return None;
+    }
+    // Round up, an expiry interval of zero would mean it never expires
+    Some(((left_ms + 999) / 1000) as u32)
+}
+
+/// A message to publish, as it is kept in the queue until it is sent.
+fn queued(message: Message, timestamp_ms: i64) -> QueuedMessage
+{
+    QueuedMessage {
+        topic: message.topic,
+        payload: message.body,
+        timestamp_ms,
+        content_type: Some(message.content_type.into()),
+        properties: message.properties
+    }
+}
+
+/// Turn a reading message into what goes on the wire. With MQTT v5 it is
+/// described by its properties, and expires on the broker once it is older
+/// than the configured expiry, counting from when it was read rather than
+/// sent. None if it has already expired.
+fn outgoing(config: &MqttConfig, message: &QueuedMessage) -> Option<paho_mqtt::Message>
+{
+    let mut builder = paho_mqtt::MessageBuilder::new()
+        .topic(message.topic.as_str())
+        .payload(message.payload.as_str())
+        .qos(config.qos);
+
+    if config.version == ProtocolVersion::V5 {
+        let mut properties = paho_mqtt::Properties::new();
+        let add = |result: paho_mqtt::Result<()>| {
+            if let Err(e) = result {
+                eprintln!("An error occurred setting message properties {:?}", e);
+            }
+        };
+
+        if let Some(ref content_type) = message.content_type {
+            add(properties.push_string(paho_mqtt::PropertyCode::ContentType, content_type));
+        }
+        for (name, value) in &message.properties {
+            add(properties.push_string_pair(paho_mqtt::PropertyCode::UserProperty, name, value));
+        }
+        if let Some(expiry) = config.message_expiry {
+            let left = expiry_left(expiry, message.timestamp_ms, Utc::now().timestamp_millis())?;
+            add(properties.push_int(paho_mqtt::PropertyCode::MessageExpiryInterval, left as i32));
+        }
+
+        builder = builder.properties(properties);
+    }
+
+    Some(builder.finalize())
+}
+
+
+/// Publishes the readings from each cycle. Keeps the broker's view of our
+/// availability up to date and, if there is an offline queue, holds on to
+/// anything that can't be delivered until the connection comes back.
+pub struct MqttPublisher<'a>
+{
+    client: paho_mqtt::Client,
+    config: &'a MqttConfig,
+    availability: Availability,
+    queue: Option<DiskQueue>,
+    reconnected: bool
+}
+
+impl<'a> MqttPublisher<'a>
+{
+    pub fn connect(config: &'a MqttConfig, client_id: &str) -> Result<MqttPublisher<'a>, Box<dyn Error>>
+    {
+        let queue = match config.queue {
+            Some(ref queue) => Some(DiskQueue::open(&queue.dir, queue.max_bytes, queue.max_age)?),
+            None => None
+        };
+
+        let client = get_client(config, client_id)?;
+        let mut availability = Availability::new();
+        availability.announce(&client, config);
+
+        Ok(MqttPublisher { client, config, availability, queue, reconnected: false })
+    }
+
+    pub fn client(&self) -> &paho_mqtt::Client
+    {
+        &self.client
+    }
+
+    pub fn is_connected(&self) -> bool
+    {
+        self.client.is_connected()
+    }
+
+    /// Whether the connection has come back since the last time this was
+    /// asked, so that retained messages may need publishing again.
+    pub fn reconnected(&mut self) -> bool
+    {
+        std::mem::replace(&mut self.reconnected, false)
+    }
+
+    fn ensure_connected(&mut self) -> bool
+    {
+        if !self.client.is_connected() {
+            self.availability.lost();
+            if let Err(_e) = self.client.reconnect() {
+                return false;
+            }
+            eprintln!("Reconnected");
+            self.reconnected = true;
+        }
+
+        self.availability.announce(&self.client, self.config);
+        true
+    }
+
+    /// Send whatever is waiting in the offline queue. Returns true if the
+    /// queue has been emptied.
+    fn replay(&mut self) -> bool
+    {
+        let client = &self.client;
+        let config = self.config;
+        let queue = match self.queue {
+            Some(ref mut queue) => queue,
+            None => return true
+        };
+
+        // Expired messages are dropped rather than sent
+        let mut failed = false;
+        match queue.replay(|m| match outgoing(config, m) {
+            Some(message) => client.publish(message).map_err(|_| failed = true),
+            None => Ok(())
+        }) {
+            Ok(0) => {},
+            Ok(sent) => eprintln!("Published {} queued messages", sent),
+            Err(e) => eprintln!("An error occurred reading the offline queue {:?}", e)
+        }
+        if failed {
+            self.availability.lost();
+        }
+
+        queue.is_empty().unwrap_or(false)
+    }
+
+    fn stash(&mut self, message: &QueuedMessage)
+    {
+        if let Some(ref mut queue) = self.queue {
+            if let Err(e) = queue.push(message) {
+                eprintln!("An error occurred queueing message {:?}", e);
+            }
+        }
+    }
+
+    /// Publish the readings from one cycle. Returns how many messages could
+    /// not be delivered, whether or not they were queued.
+    pub fn publish(&mut self, payload: &Payload) -> usize
+    {
+        let messages = match self.config.topic_layout.messages(&self.config.topic, payload) {
+            Ok(messages) => messages,
+            Err(e) => {
+                eprintln!("An error occurred serializing readings {:?}", e);
+                return 1;
+            }
+        };
+
+        let messages = messages.into_iter()
+            .map(|message| queued(message, payload.time.timestamp_ms))
+            .collect();
+
+        self.send(messages)
+    }
+
+    /// Publish a sensor being added or removed on the events topic. Returns
+    /// 1 if it could not be delivered, whether or not it was queued.
+    pub fn publish_event(&mut self, event: &SensorEvent) -> usize
+    {
+        let payload = match serde_json::to_string(event) {
+            Ok(payload) => payload,
+            Err(e) => {
+                eprintln!("An error occurred serializing event {:?}", e);
+                return 1;
+            }
+        };
+
+        let message = Message::new(events_topic(&self.config.topic), payload, JSON);
+        self.send(vec![queued(message, event.time.timestamp_ms)])
+    }
+
+    /// Deliver `messages`, queueing whatever can't be. Returns how many could
+    /// not be delivered.
+    fn send(&mut self, messages: Vec<QueuedMessage>) -> usize
+    {
+        // Nothing new goes out while older messages are still queued, so
+        // that subscribers see them in order
+        let mut deliver = self.ensure_connected() && self.replay();
+        let mut failed = 0;
+
+        for message in messages {
+            if deliver {
+                let outgoing = match outgoing(self.config, &message) {
+                    Some(outgoing) => outgoing,
+                    None => continue
+                };
+                match self.client.publish(outgoing) {
+                    Ok(()) => continue,
+                    Err(e) => {
+                        eprintln!("An error occurred publishing message {:?}", e);
+                        self.availability.lost();
+                        deliver = false;
+                    }
+                }
+            }
+
+            failed += 1;
+            self.stash(&message);
+        }
+
+        failed
+    }
+
+    pub fn shutdown(self)
+    {
+        shutdown(&self.client, self.config);
+    }
+}
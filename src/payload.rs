@@ -0,0 +1,162 @@
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use serde::Serialize;
+
+use crate::sensors::SensorReport;
+
+
+/// Which clock timestamps are rendered in. The epoch milliseconds are the
+/// same either way, only the RFC3339 string changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeZone
+{
+    Utc,
+    Local
+}
+
+impl FromStr for TimeZone
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TimeZone, String>
+    {
+        match s.to_lowercase().as_str() {
+            "utc" => Ok(TimeZone::Utc),
+            "local" => Ok(TimeZone::Local),
+            _ => Err(format!("unknown time zone '{}', expected utc or local", s))
+        }
+    }
+}
+
+impl fmt::Display for TimeZone
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self {
+            TimeZone::Utc => write!(f, "utc"),
+            TimeZone::Local => write!(f, "local")
+        }
+    }
+}
+
+
+/// The moment a reading was taken.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct Timestamp
+{
+    /// RFC3339 with millisecond precision.
+    pub timestamp: String,
+    /// Milliseconds since the Unix epoch.
+    pub timestamp_ms: i64
+}
+
+impl Timestamp
+{
+    pub fn new(time: DateTime<Utc>, zone: TimeZone) -> Timestamp
+    {
+        let timestamp = match zone {
+            TimeZone::Utc => time.to_rfc3339_opts(SecondsFormat::Millis, true),
+            TimeZone::Local => time.with_timezone(&Local)
+                .to_rfc3339_opts(SecondsFormat::Millis, false)
+        };
+
+        Timestamp { timestamp, timestamp_ms: time.timestamp_millis() }
+    }
+
+    pub fn now(zone: TimeZone) -> Timestamp
+    {
+        Timestamp::new(Utc::now(), zone)
+    }
+}
+
+
+/// A sensor report as it appears in the payload, optionally with the time
+/// that particular sensor was read.
+#[derive(Serialize, Clone, Debug)]
+pub struct SensorEntry
+{
+    /// The sensor's own id, if it is keyed by an alias.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(flatten)]
+    pub report: SensorReport,
+    #[serde(flatten)]
+    pub time: Option<Timestamp>
+}
+
+impl SensorEntry
+{
+    /// The sensor's own id, given the key the entry is stored under.
+    pub fn sensor_id<'a>(&'a self, key: &'a str) -> &'a str
+    {
+        self.id.as_deref().unwrap_or(key)
+    }
+
+    /// The sensor's alias, given the key the entry is stored under.
+    pub fn alias<'a>(&self, key: &'a str) -> Option<&'a str>
+    {
+        self.id.as_ref().map(|_| key)
+    }
+}
+
+
+/// A sensor's entry from the payload with a timestamp always attached, the
+/// sensor's own if it has one, otherwise the cycle's.
+#[derive(Serialize, Debug)]
+pub struct SensorMessage<'a>
+{
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<&'a str>,
+    #[serde(flatten)]
+    pub report: &'a SensorReport,
+    #[serde(flatten)]
+    pub time: &'a Timestamp
+}
+
+
+/// The message published for one read cycle.
+#[derive(Serialize, Clone, Debug)]
+pub struct Payload
+{
+    #[serde(flatten)]
+    pub time: Timestamp,
+    pub sensors: BTreeMap<String, SensorEntry>
+}
+
+impl Payload
+{
+    pub fn new(time: Timestamp) -> Payload
+    {
+        Payload { time, sensors: BTreeMap::new() }
+    }
+
+    pub fn insert(&mut self, id: &str, report: SensorReport, time: Option<Timestamp>)
+    {
+        self.sensors.insert(id.into(), SensorEntry { id: None, report, time });
+    }
+
+    /// Insert a sensor under its alias, keeping its id in the entry.
+    pub fn insert_alias(&mut self, alias: &str, id: &str, report: SensorReport, time: Option<Timestamp>)
+    {
+        self.sensors.insert(alias.into(), SensorEntry { id: Some(id.into()), report, time });
+    }
+
+    /// One sensor's entry on its own, as published on a per-sensor topic.
+    pub fn sensor_message<'a>(&'a self, entry: &'a SensorEntry) -> SensorMessage<'a>
+    {
+        SensorMessage {
+            id: entry.id.as_deref(),
+            report: &entry.report,
+            time: entry.time.as_ref().unwrap_or(&self.time)
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String>
+    {
+        serde_json::to_string(self)
+    }
+}
@@ -0,0 +1,182 @@
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+
+/// A message that could not be published, kept until it can be.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct QueuedMessage
+{
+    pub topic: String,
+    pub payload: String,
+    /// When the reading in the payload was taken, in milliseconds since the
+    /// Unix epoch. Used to expire old messages.
+    pub timestamp_ms: i64,
+    #[serde(default)]
+    pub content_type: Option<String>,
+    /// MQTT v5 user properties.
+    #[serde(default)]
+    pub properties: Vec<(String, String)>
+}
+
+
+struct Entry
+{
+    path: PathBuf,
+    timestamp_ms: i64,
+    size: u64
+}
+
+
+/// A bounded first-in first-out queue of messages, kept in a directory so
+/// that it survives restarts. Each message is a file named
+/// `<timestamp_ms>-<sequence>.msg`, so the directory listing sorts into the
+/// order the messages were queued in.
+pub struct DiskQueue
+{
+    dir: PathBuf,
+    max_bytes: u64,
+    max_age: Duration,
+    next_sequence: u64
+}
+
+impl DiskQueue
+{
+    /// Open the queue in `dir`, creating the directory if needed and picking
+    /// up any messages left over from a previous run.
+    pub fn open(dir: &Path, max_bytes: u64, max_age: Duration) -> io::Result<DiskQueue>
+    {
+        fs::create_dir_all(dir)?;
+
+        // Messages that were being written when we last stopped
+        for item in fs::read_dir(dir)? {
+            let path = item?.path();
+            if path.extension().is_some_and(|extension| extension == "tmp") {
+                fs::remove_file(&path)?;
+            }
+        }
+
+        let mut queue = DiskQueue {
+            dir: dir.to_path_buf(),
+            max_bytes,
+            max_age,
+            next_sequence: 0
+        };
+
+        queue.next_sequence = queue.entries()?.iter()
+            .filter_map(|e| DiskQueue::parse_name(&e.path).map(|(_, seq)| seq + 1))
+            .max()
+            .unwrap_or(0);
+
+        Ok(queue)
+    }
+
+    fn parse_name(path: &Path) -> Option<(i64, u64)>
+    {
+        if path.extension()? != "msg" {
+            return None;
+        }
+        let stem = path.file_stem()?.to_str()?;
+        let mut parts = stem.splitn(2, '-');
+        let timestamp_ms = parts.next()?.parse().ok()?;
+        let sequence = parts.next()?.parse().ok()?;
+        Some((timestamp_ms, sequence))
+    }
+
+    /// The queued messages, oldest first.
+    fn entries(&self) -> io::Result<Vec<Entry>>
+    {
+        let mut entries = Vec::new();
+
+        for item in fs::read_dir(&self.dir)? {
+            let item = item?;
+            let path = item.path();
+            if let Some((timestamp_ms, _)) = DiskQueue::parse_name(&path) {
+                entries.push(Entry { path, timestamp_ms, size: item.metadata()?.len() });
+            }
+        }
+
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(entries)
+    }
+
+    /// Drop messages that are too old, then the oldest messages until the
+    /// queue fits within its size limit.
+    fn prune(&self) -> io::Result<()>
+    {
+        let oldest_allowed = Utc::now().timestamp_millis() - self.max_age.as_millis() as i64;
+
+        let mut entries = self.entries()?;
+        let mut total: u64 = entries.iter().map(|e| e.size).sum();
+
+        entries.reverse();
+        while let Some(entry) = entries.pop() {
+            if entry.timestamp_ms >= oldest_allowed && total <= self.max_bytes {
+                break;
+            }
+            fs::remove_file(&entry.path)?;
+            total -= entry.size;
+        }
+
+        Ok(())
+    }
+
+    pub fn push(&mut self, message: &QueuedMessage) -> io::Result<()>
+    {
+        let name = format!("{:015}-{:010}", message.timestamp_ms, self.next_sequence);
+        self.next_sequence += 1;
+
+        // Write then rename, so a crash never leaves a half written message
+        let tmp = self.dir.join(format!("{}.tmp", name));
+        fs::write(&tmp, serde_json::to_vec(message)?)?;
+        fs::rename(&tmp, self.dir.join(format!("{}.msg", name)))?;
+
+        self.prune()
+    }
+
+    pub fn len(&self) -> io::Result<usize>
+    {
+        Ok(self.entries()?.len())
+    }
+
+    pub fn is_empty(&self) -> io::Result<bool>
+    {
+        Ok(self.len()? == 0)
+    }
+
+    /// Hand the queued messages to `send`, oldest first, removing each one
+    /// once it has been sent. Stops at the first message that can't be sent
+    /// and leaves it at the head of the queue. Returns the number sent.
+    pub fn replay<F, E>(&mut self, mut send: F) -> io::Result<usize>
+        where F: FnMut(&QueuedMessage) -> Result<(), E>
+    {
+        self.prune()?;
+
+        let mut sent = 0;
+        for entry in self.entries()? {
+            let message: QueuedMessage = match fs::read(&entry.path)
+                .and_then(|data| serde_json::from_slice(&data).map_err(io::Error::from)) {
+                Ok(message) => message,
+                Err(e) => {
+                    eprintln!("Discarding unreadable queued message {}: {}", entry.path.display(), e);
+                    fs::remove_file(&entry.path)?;
+                    continue;
+                }
+            };
+
+            if send(&message).is_err() {
+                break;
+            }
+
+            fs::remove_file(&entry.path)?;
+            sent += 1;
+        }
+
+        Ok(sent)
+    }
+}
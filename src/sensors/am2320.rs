@@ -0,0 +1,140 @@
+
+use std::ffi::OsStr;
+use std::io;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use i2cdev::core::I2CDevice;
+use i2cdev::linux::{LinuxI2CDevice, LinuxI2CError};
+
+use super::{Sensor, SensorError, Reading, Quantity, QuantityKind, TEMPERATURE, HUMIDITY};
+
+
+/// I2C address of the AM2320, which is fixed by the device.
+const AM2320_ADDRESS: u16 = 0x5c;
+
+/// Modbus "read registers" function code used by the AM2320.
+const AM2320_READ_REGISTERS: u8 = 0x03;
+
+pub const SENSOR_TYPE: &str = "am2320";
+
+/// Every AM2320 id is this followed by the name of its I2C bus.
+pub const ID_PREFIX: &str = "am2320-";
+
+pub struct AM2320Sensor
+{
+    id: String,
+    device: PathBuf
+}
+
+fn reading(temperature: f32, humidity: f32) -> Reading
+{
+    Reading::new(SENSOR_TYPE, vec![
+        Quantity::temperature(temperature),
+        Quantity::humidity(humidity)
+    ])
+}
+
+/// CRC16 (Modbus variant) as used by the AM2320 to protect its responses.
+pub fn am2320_crc16(data: &[u8]) -> u16
+{
+    let mut crc = 0xffffu16;
+    for byte in data {
+        crc ^= *byte as u16;
+        for _ in 0..8 {
+            if crc & 0x0001 != 0 {
+                crc >>= 1;
+                crc ^= 0xa001;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+    crc
+}
+
+/// Decode the response to reading the four humidity and temperature
+/// registers: function code, byte count, 4 data bytes, CRC (low byte first).
+pub fn parse_response(buf: &[u8; 8]) -> Result<Reading, SensorError>
+{
+    if buf[0] != AM2320_READ_REGISTERS || buf[1] != 4 {
+        return Err(SensorError::Malformed);
+    }
+
+    let crc = u16::from_le_bytes([buf[6], buf[7]]);
+    if crc != am2320_crc16(&buf[..6]) {
+        return Err(SensorError::Checksum);
+    }
+
+    let raw_humidity = u16::from_be_bytes([buf[2], buf[3]]);
+    let raw_temperature = u16::from_be_bytes([buf[4], buf[5]]);
+
+    // Temperature is sign-magnitude, not two's complement
+    let mut temperature = (raw_temperature & 0x7fff) as f32 / 10.0f32;
+    if raw_temperature & 0x8000 != 0 {
+        temperature = -temperature;
+    }
+
+    Ok(reading(temperature, (raw_humidity as f32) / 10.0f32))
+}
+
+impl AM2320Sensor {
+    pub fn new(device: PathBuf) -> AM2320Sensor
+    {
+        let id = format!("{}{}", ID_PREFIX, device.file_name()
+            .map(OsStr::to_string_lossy)
+            .unwrap_or("i2c".into()));
+        AM2320Sensor { id, device }
+    }
+
+    /// Read the four humidity and temperature registers from the device.
+    ///
+    /// The AM2320 sleeps between measurements and NACKs the first transfer,
+    /// so we have to poke it awake before sending the read command.
+    fn read_registers(&self) -> Result<[u8; 8], SensorError>
+    {
+        if !self.device.exists() {
+            return Err(SensorError::NotFound);
+        }
+
+        let i2c_error = |e: LinuxI2CError| SensorError::Io(io::Error::other(e));
+
+        let mut dev = LinuxI2CDevice::new(&self.device, AM2320_ADDRESS)
+            .map_err(i2c_error)?;
+
+        // The wake-up write is expected to fail.
+        let _ = dev.write(&[0x00]);
+        thread::sleep(Duration::from_millis(1));
+
+        dev.write(&[AM2320_READ_REGISTERS, 0x00, 0x04]).map_err(i2c_error)?;
+        thread::sleep(Duration::from_millis(2));
+
+        let mut buf = [0u8; 8];
+        dev.read(&mut buf).map_err(i2c_error)?;
+        Ok(buf)
+    }
+}
+
+impl Sensor for AM2320Sensor {
+
+    fn identifier(&self) -> &str
+    {
+        &self.id
+    }
+
+    fn sensor_type(&self) -> &'static str
+    {
+        SENSOR_TYPE
+    }
+
+    fn quantities(&self) -> &'static [QuantityKind]
+    {
+        &[TEMPERATURE, HUMIDITY]
+    }
+
+    fn read(&self) -> Result<Reading, SensorError>
+    {
+        parse_response(&self.read_registers()?)
+    }
+}
@@ -0,0 +1,258 @@
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::{Sensor, SensorError, Reading, Quantity, QuantityKind, TEMPERATURE};
+
+
+/// Where the w1-therm driver exposes the 1-Wire devices on a standard system.
+pub const DEFAULT_DEVICE_PATH: &str = "/sys/bus/w1/devices/";
+
+pub const SENSOR_TYPE: &str = "ds18b20";
+
+/// Every DS18B20 id starts with its 1-Wire family code.
+pub const ID_PREFIX: &str = "28-";
+
+/// Raw temperature register value the device holds after power on, before
+/// any conversion has taken place. Reads as 85°C.
+const POWER_ON_RESET_RAW: i16 = 0x0550;
+
+/// Raw temperature register value produced when the data line is held high
+/// during the read. Reads as 127.9375°C.
+const BUS_ERROR_RAW: i16 = 0x07ff;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScratchpadError
+{
+    /// The w1_slave file did not have the layout written by w1-therm.
+    Malformed,
+    /// The kernel driver reported that the scratchpad CRC did not match.
+    DriverCrc,
+    /// The CRC we computed over the scratchpad bytes did not match.
+    CrcMismatch { expected: u8, computed: u8 },
+    /// The device has not completed a conversion since it was powered up.
+    PowerOnReset,
+    /// The scratchpad was read from a stuck or disconnected bus.
+    BusError
+}
+
+impl fmt::Display for ScratchpadError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self {
+            ScratchpadError::Malformed => write!(f, "malformed w1_slave contents"),
+            ScratchpadError::DriverCrc => write!(f, "driver reported a CRC error"),
+            ScratchpadError::CrcMismatch { expected, computed } => write!(
+                f, "scratchpad CRC mismatch (expected {:02x}, computed {:02x})", expected, computed
+            ),
+            ScratchpadError::PowerOnReset => write!(f, "power-on-reset value (85°C)"),
+            ScratchpadError::BusError => write!(f, "bus error value (127.94°C)")
+        }
+    }
+}
+
+impl Error for ScratchpadError {}
+
+impl From<ScratchpadError> for SensorError
+{
+    fn from(err: ScratchpadError) -> SensorError
+    {
+        match err {
+            ScratchpadError::Malformed => SensorError::Malformed,
+            ScratchpadError::DriverCrc => SensorError::Checksum,
+            ScratchpadError::CrcMismatch { .. } => SensorError::Checksum,
+            ScratchpadError::PowerOnReset => SensorError::PowerOnReset,
+            ScratchpadError::BusError => SensorError::BusError
+        }
+    }
+}
+
+
+/// Dallas/Maxim CRC8 (polynomial x^8 + x^5 + x^4 + 1) used by 1-Wire devices.
+fn w1_crc8(data: &[u8]) -> u8
+{
+    let mut crc = 0u8;
+    for byte in data {
+        let mut byte = *byte;
+        for _ in 0..8 {
+            let mix = (crc ^ byte) & 0x01;
+            crc >>= 1;
+            if mix != 0 {
+                crc ^= 0x8c;
+            }
+            byte >>= 1;
+        }
+    }
+    crc
+}
+
+
+/// The nine bytes of the DS18B20 scratchpad memory, as dumped by the w1-therm
+/// driver in the first line of w1_slave:
+///
+/// ```text
+/// 72 01 4b 46 7f ff 0c 10 c6 : crc=c6 YES
+/// 72 01 4b 46 7f ff 0c 10 c6 t=23125
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scratchpad
+{
+    bytes: [u8; 9]
+}
+
+impl Scratchpad
+{
+    /// Parse the contents of a w1_slave file and verify the scratchpad CRC.
+    pub fn parse(contents: &str) -> Result<Scratchpad, ScratchpadError>
+    {
+        let line = contents.lines().next().ok_or(ScratchpadError::Malformed)?;
+
+        let mut parts = line.splitn(2, ':');
+        let hex = parts.next().ok_or(ScratchpadError::Malformed)?;
+        let status = parts.next().ok_or(ScratchpadError::Malformed)?;
+
+        let mut bytes = [0u8; 9];
+        let mut count = 0;
+        for item in hex.split_whitespace() {
+            if count == bytes.len() {
+                return Err(ScratchpadError::Malformed);
+            }
+            bytes[count] = u8::from_str_radix(item, 16)
+                .map_err(|_| ScratchpadError::Malformed)?;
+            count += 1;
+        }
+        if count != bytes.len() {
+            return Err(ScratchpadError::Malformed);
+        }
+
+        if !status.trim_end().ends_with("YES") {
+            return Err(ScratchpadError::DriverCrc);
+        }
+
+        // Don't trust the driver's verdict alone, check the bytes we were given
+        let computed = w1_crc8(&bytes[..8]);
+        if computed != bytes[8] {
+            return Err(ScratchpadError::CrcMismatch { expected: bytes[8], computed });
+        }
+
+        // All zeros passes the CRC check, but is what a shorted bus reads as
+        if bytes.iter().all(|b| *b == 0) {
+            return Err(ScratchpadError::BusError);
+        }
+
+        Ok(Scratchpad { bytes })
+    }
+
+    pub fn bytes(&self) -> &[u8; 9]
+    {
+        &self.bytes
+    }
+
+    /// Conversion resolution in bits, from the configuration register.
+    pub fn resolution(&self) -> u32
+    {
+        9 + ((self.bytes[4] >> 5) & 0x03) as u32
+    }
+
+    /// The temperature register exactly as read, in sixteenths of a degree.
+    fn register(&self) -> i16
+    {
+        i16::from_le_bytes([self.bytes[0], self.bytes[1]])
+    }
+
+    /// The temperature register, in sixteenths of a degree, with the bits
+    /// that are undefined at the configured resolution cleared.
+    pub fn raw_temperature(&self) -> i16
+    {
+        let undefined = 12 - self.resolution();
+        self.register() & !((1i16 << undefined) - 1)
+    }
+
+    /// The temperature in degrees Celsius, rejecting the values the device
+    /// reports when it hasn't actually measured anything.
+    pub fn temperature(&self) -> Result<f32, ScratchpadError>
+    {
+        match self.register() {
+            POWER_ON_RESET_RAW => Err(ScratchpadError::PowerOnReset),
+            BUS_ERROR_RAW => Err(ScratchpadError::BusError),
+            _ => Ok((self.raw_temperature() as f32) / 16.0f32)
+        }
+    }
+}
+
+
+pub struct DS18B20Sensor
+{
+    id: String,
+    path: PathBuf
+}
+
+fn reading(temperature: f32) -> Reading
+{
+    Reading::new(SENSOR_TYPE, vec![Quantity::temperature(temperature)])
+}
+
+impl DS18B20Sensor {
+    pub fn new(device_path: &Path, id_os: &str) -> DS18B20Sensor
+    {
+        DS18B20Sensor {
+            id: id_os.into(),
+            path: device_path.join(id_os).join("w1_slave")
+        }
+    }
+}
+
+impl Sensor for DS18B20Sensor {
+
+    fn identifier(&self) -> &str
+    {
+         &self.id
+    }
+
+    fn sensor_type(&self) -> &'static str
+    {
+        SENSOR_TYPE
+    }
+
+    fn quantities(&self) -> &'static [QuantityKind]
+    {
+        &[TEMPERATURE]
+    }
+
+    fn read(&self) -> Result<Reading, SensorError>
+    {
+        let contents = fs::read(&self.path)?;
+        let string_contents = String::from_utf8(contents)
+            .map_err(|_| SensorError::Malformed)?;
+
+        let temperature = Scratchpad::parse(&string_contents)?.temperature()?;
+        Ok(reading(temperature))
+    }
+}
+
+/// Find all the DS18B20 devices (family code 28) on the 1-Wire bus, whose
+/// devices are listed under `device_path`.
+pub fn discover(device_path: &Path) -> io::Result<Vec<DS18B20Sensor>>
+{
+    let mut result = Vec::new();
+
+    for dev in fs::read_dir(device_path)?.flatten() {
+        let name = dev.file_name();
+        let id = name.to_string_lossy();
+        if !id.starts_with(ID_PREFIX) {
+             continue;
+        }
+
+        result.push(DS18B20Sensor::new(device_path, &id));
+    }
+
+    // read_dir makes no promises about order, keep the payload stable
+    result.sort_by(|a, b| a.id.cmp(&b.id));
+
+    Ok(result)
+}
@@ -0,0 +1,293 @@
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use serde::Serialize;
+
+pub mod ds18b20;
+pub mod am2320;
+mod retry;
+
+pub use ds18b20::DS18B20Sensor;
+pub use am2320::AM2320Sensor;
+pub use retry::RetryPolicy;
+
+
+/// Something a sensor can measure, and the unit it reports it in.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QuantityKind
+{
+    pub name: &'static str,
+    pub unit: &'static str
+}
+
+pub const TEMPERATURE: QuantityKind = QuantityKind { name: "temperature", unit: "°C" };
+pub const HUMIDITY: QuantityKind = QuantityKind { name: "humidity", unit: "%" };
+
+
+/// A single named measurement, such as a temperature or a relative humidity,
+/// together with the unit it is expressed in.
+#[derive(Serialize, Clone, Debug)]
+pub struct Quantity
+{
+    pub name: &'static str,
+    pub value: f32,
+    pub unit: &'static str
+}
+
+impl Quantity
+{
+    pub fn new(name: &'static str, value: f32, unit: &'static str) -> Quantity
+    {
+        Quantity { name, value, unit }
+    }
+
+    pub fn of(kind: QuantityKind, value: f32) -> Quantity
+    {
+        Quantity::new(kind.name, value, kind.unit)
+    }
+
+    pub fn temperature(value: f32) -> Quantity
+    {
+        Quantity::of(TEMPERATURE, value)
+    }
+
+    pub fn humidity(value: f32) -> Quantity
+    {
+        Quantity::of(HUMIDITY, value)
+    }
+}
+
+
+/// The result of reading a sensor once. Every sensor produces the same
+/// reading type, whatever it measures, so that they can all be kept in one
+/// collection and serialized into one payload.
+#[derive(Serialize, Clone, Debug)]
+pub struct Reading
+{
+    #[serde(rename = "type")]
+    pub sensor_type: &'static str,
+    pub quantities: Vec<Quantity>
+}
+
+impl Reading
+{
+    pub fn new(sensor_type: &'static str, quantities: Vec<Quantity>) -> Reading
+    {
+        Reading { sensor_type, quantities }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Quantity>
+    {
+        self.quantities.iter().find(|q| q.name == name)
+    }
+}
+
+
+/// Everything that can go wrong reading a sensor.
+#[derive(Debug)]
+pub enum SensorError
+{
+    /// The device is no longer present.
+    NotFound,
+    /// Talking to the device failed.
+    Io(io::Error),
+    /// The data read from the device failed its checksum.
+    Checksum,
+    /// The device responded, but not in the format we expected.
+    Malformed,
+    /// The device has not taken a measurement since it was powered up.
+    PowerOnReset,
+    /// The data was read from a stuck or disconnected bus.
+    BusError
+}
+
+impl SensorError
+{
+    /// Short machine readable name for the error, used in payloads.
+    pub fn kind(&self) -> &'static str
+    {
+        match self {
+            SensorError::NotFound => "not_found",
+            SensorError::Io(_) => "io",
+            SensorError::Checksum => "checksum",
+            SensorError::Malformed => "malformed",
+            SensorError::PowerOnReset => "power_on_reset",
+            SensorError::BusError => "bus_error"
+        }
+    }
+}
+
+impl fmt::Display for SensorError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self {
+            SensorError::NotFound => write!(f, "device not found"),
+            SensorError::Io(e) => write!(f, "I/O error: {}", e),
+            SensorError::Checksum => write!(f, "checksum mismatch"),
+            SensorError::Malformed => write!(f, "malformed response"),
+            SensorError::PowerOnReset => write!(f, "power-on-reset value"),
+            SensorError::BusError => write!(f, "bus error value")
+        }
+    }
+}
+
+impl Error for SensorError
+{
+    fn source(&self) -> Option<&(dyn Error + 'static)>
+    {
+        match self {
+            SensorError::Io(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for SensorError
+{
+    fn from(err: io::Error) -> SensorError
+    {
+        match err.kind() {
+            io::ErrorKind::NotFound => SensorError::NotFound,
+            _ => SensorError::Io(err)
+        }
+    }
+}
+
+
+/// The outcome of reading a sensor, in the form it is published. Consumers
+/// can tell from `status` whether the quantities are present, and from
+/// `error` why not.
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum SensorReport
+{
+    Ok(Reading),
+    Error {
+        #[serde(rename = "type")]
+        sensor_type: &'static str,
+        error: &'static str,
+        message: String
+    }
+}
+
+impl SensorReport
+{
+    pub fn new(sensor_type: &'static str, result: Result<Reading, SensorError>) -> SensorReport
+    {
+        match result {
+            Ok(reading) => SensorReport::Ok(reading),
+            Err(e) => SensorReport::Error {
+                sensor_type,
+                error: e.kind(),
+                message: e.to_string()
+            }
+        }
+    }
+
+    pub fn sensor_type(&self) -> &'static str
+    {
+        match self {
+            SensorReport::Ok(reading) => reading.sensor_type,
+            SensorReport::Error { sensor_type, .. } => sensor_type
+        }
+    }
+}
+
+
+pub trait Sensor
+{
+    fn identifier(&self) -> &str;
+    fn sensor_type(&self) -> &'static str;
+    /// What the sensor measures, in the order it appears in its readings.
+    fn quantities(&self) -> &'static [QuantityKind];
+    fn read(&self) -> Result<Reading, SensorError>;
+
+    fn report(&self) -> SensorReport
+    {
+        SensorReport::new(self.sensor_type(), self.read())
+    }
+
+    fn read_to_string(&self) -> String
+    {
+        serde_json::to_string(&self.report()).unwrap()
+    }
+}
+
+
+/// What the configuration says about a sensor, for the people looking at
+/// its readings.
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SensorMetadata
+{
+    /// Used in place of the sensor id in payloads and topics.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>
+}
+
+impl SensorMetadata
+{
+    /// What the sensor is known by: its alias if it has one, otherwise `id`.
+    pub fn key<'a>(&'a self, id: &'a str) -> &'a str
+    {
+        self.alias.as_deref().unwrap_or(id)
+    }
+}
+
+/// A sensor as it is described to the outside world.
+#[derive(Serialize, Clone, Debug)]
+pub struct SensorInfo
+{
+    pub id: String,
+    #[serde(rename = "type")]
+    pub sensor_type: &'static str,
+    pub quantities: &'static [QuantityKind],
+    #[serde(flatten)]
+    pub metadata: SensorMetadata
+}
+
+impl SensorInfo
+{
+    pub fn new(sensor: &dyn Sensor, metadata: &SensorMetadata) -> SensorInfo
+    {
+        SensorInfo {
+            id: sensor.identifier().into(),
+            sensor_type: sensor.sensor_type(),
+            quantities: sensor.quantities(),
+            metadata: metadata.clone()
+        }
+    }
+
+    pub fn key(&self) -> &str
+    {
+        self.metadata.key(&self.id)
+    }
+}
+
+
+pub type SensorList = Vec<Box<dyn Sensor>>;
+
+pub fn get_sensors(w1_device_path: &Path, am2320_device: Option<&Path>)
+    -> Result<SensorList, Box<dyn Error>>
+{
+    let mut result: SensorList = Vec::new();
+
+    for sensor in ds18b20::discover(w1_device_path)? {
+        result.push(Box::new(sensor));
+    }
+
+    if let Some(device) = am2320_device {
+        result.push(Box::new(AM2320Sensor::new(device.to_path_buf())));
+    }
+
+    Ok(result)
+}
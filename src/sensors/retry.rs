@@ -0,0 +1,77 @@
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{Sensor, SensorError, Reading};
+
+
+/// How hard to try to get a reading out of a sensor before giving up on it
+/// for this cycle.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy
+{
+    /// Number of additional attempts after the first read fails.
+    pub retries: u32,
+    /// Delay before the first retry, doubled for each retry after that.
+    pub backoff: Duration
+}
+
+impl RetryPolicy
+{
+    pub fn new(retries: u32, backoff: Duration) -> RetryPolicy
+    {
+        RetryPolicy { retries, backoff }
+    }
+
+    /// Never retry, a failed read is reported straight away.
+    pub fn none() -> RetryPolicy
+    {
+        RetryPolicy::new(0, Duration::from_secs(0))
+    }
+
+    fn is_retryable(err: &SensorError) -> bool
+    {
+        // A device that has gone away isn't going to come back in a few
+        // milliseconds, everything else might be a glitch on the bus.
+        !matches!(err, SensorError::NotFound)
+    }
+
+    /// Read the sensor, retrying failures according to the policy. No retry
+    /// is started if it cannot finish its backoff before `deadline`, so that
+    /// a single bad sensor can't hold up the whole cycle.
+    pub fn read(&self, sensor: &dyn Sensor, deadline: Instant) -> Result<Reading, SensorError>
+    {
+        let mut delay = self.backoff;
+        let mut attempt = 0;
+
+        loop {
+            let err = match sensor.read() {
+                Ok(reading) => return Ok(reading),
+                Err(e) => e
+            };
+
+            if attempt >= self.retries || !RetryPolicy::is_retryable(&err) {
+                return Err(err);
+            }
+
+            let now = Instant::now();
+            if now + delay >= deadline {
+                return Err(err);
+            }
+
+            eprintln!("Retrying sensor {} after error: {}", sensor.identifier(), err);
+            thread::sleep(delay);
+
+            attempt += 1;
+            delay *= 2;
+        }
+    }
+}
+
+impl Default for RetryPolicy
+{
+    fn default() -> RetryPolicy
+    {
+        RetryPolicy::new(2, Duration::from_millis(250))
+    }
+}
@@ -0,0 +1,61 @@
+
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use signal_hook::consts::{SIGINT, SIGTERM};
+
+
+/// How often a sleep checks whether it should stop early.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+
+/// Set once SIGTERM or SIGINT has been received. The read loop checks this
+/// between cycles, so a stop request lets the current cycle finish. A second
+/// signal while we are shutting down exits immediately.
+#[derive(Clone)]
+pub struct Shutdown
+{
+    flag: Arc<AtomicBool>
+}
+
+impl Shutdown
+{
+    pub fn register() -> io::Result<Shutdown>
+    {
+        let flag = Arc::new(AtomicBool::new(false));
+
+        for signal in &[SIGTERM, SIGINT] {
+            // Order matters: the conditional exit must see the flag before
+            // the first signal sets it.
+            signal_hook::flag::register_conditional_shutdown(*signal, 1, Arc::clone(&flag))?;
+            signal_hook::flag::register(*signal, Arc::clone(&flag))?;
+        }
+
+        Ok(Shutdown { flag })
+    }
+
+    pub fn requested(&self) -> bool
+    {
+        self.flag.load(Ordering::SeqCst)
+    }
+
+    /// Sleep for `duration`, waking early if shutdown is requested. Returns
+    /// true if we should carry on.
+    pub fn sleep(&self, duration: Duration) -> bool
+    {
+        let end = Instant::now() + duration;
+
+        while !self.requested() {
+            let now = Instant::now();
+            if now >= end {
+                return true;
+            }
+            thread::sleep(POLL_INTERVAL.min(end - now));
+        }
+
+        false
+    }
+}
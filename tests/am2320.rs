@@ -0,0 +1,55 @@
+
+use sensor_reader::sensors::SensorError;
+use sensor_reader::sensors::am2320::{am2320_crc16, parse_response};
+
+
+/// 50.0% and 25.0°C, with a valid CRC.
+const RESPONSE: [u8; 8] = [0x03, 0x04, 0x01, 0xf4, 0x00, 0xfa, 0x31, 0xa5];
+
+/// 40.0% and -10.1°C, with a valid CRC.
+const RESPONSE_MINUS_10_1: [u8; 8] = [0x03, 0x04, 0x01, 0x90, 0x80, 0x65, 0x51, 0xd2];
+
+
+#[test]
+fn crc16_is_the_modbus_crc()
+{
+    // The standard check value for CRC-16/MODBUS
+    assert_eq!(am2320_crc16(b"123456789"), 0x4b37);
+    assert_eq!(am2320_crc16(&RESPONSE[..6]), 0xa531);
+}
+
+#[test]
+fn response_is_decoded()
+{
+    let reading = parse_response(&RESPONSE).unwrap();
+    assert_eq!(reading.get("humidity").unwrap().value, 50.0);
+    assert_eq!(reading.get("temperature").unwrap().value, 25.0);
+}
+
+#[test]
+fn negative_temperature_is_sign_magnitude()
+{
+    let reading = parse_response(&RESPONSE_MINUS_10_1).unwrap();
+    assert_eq!(reading.get("temperature").unwrap().value, -10.1);
+    assert_eq!(reading.get("humidity").unwrap().value, 40.0);
+}
+
+#[test]
+fn bad_crc_is_rejected()
+{
+    let mut response = RESPONSE;
+    response[7] ^= 0x01;
+    assert!(matches!(parse_response(&response), Err(SensorError::Checksum)));
+}
+
+#[test]
+fn wrong_function_code_is_rejected()
+{
+    let mut response = RESPONSE;
+    response[0] = 0x10;
+    assert!(matches!(parse_response(&response), Err(SensorError::Malformed)));
+
+    let mut response = RESPONSE;
+    response[1] = 2;
+    assert!(matches!(parse_response(&response), Err(SensorError::Malformed)));
+}
@@ -0,0 +1,160 @@
+
+mod common;
+
+use std::collections::BTreeMap;
+
+use sensor_reader::api::Api;
+use sensor_reader::config::{DeviceConfig, SensorsConfig};
+use sensor_reader::sensors::{Sensor, SensorError, SensorList, SensorMetadata, Reading, QuantityKind};
+use sensor_reader::sensors::{HUMIDITY, TEMPERATURE};
+
+use common::*;
+
+
+struct FakeSensor
+{
+    id: &'static str,
+    sensor_type: &'static str,
+    quantities: &'static [QuantityKind]
+}
+
+impl Sensor for FakeSensor
+{
+    fn identifier(&self) -> &str
+    {
+        self.id
+    }
+
+    fn sensor_type(&self) -> &'static str
+    {
+        self.sensor_type
+    }
+
+    fn quantities(&self) -> &'static [QuantityKind]
+    {
+        self.quantities
+    }
+
+    fn read(&self) -> Result<Reading, SensorError>
+    {
+        Err(SensorError::NotFound)
+    }
+}
+
+fn sensors_config(devices: BTreeMap<String, DeviceConfig>) -> SensorsConfig
+{
+    SensorsConfig { w1_device_path: "/sys/bus/w1/devices".into(), am2320_device: None, devices }
+}
+
+fn api_with(config: &SensorsConfig) -> Api
+{
+    let sensors: SensorList = vec![
+        Box::new(FakeSensor { id: "28-0316a2794eff", sensor_type: "ds18b20", quantities: &[TEMPERATURE] }),
+        Box::new(FakeSensor { id: "am2320-i2c-1", sensor_type: "am2320", quantities: &[TEMPERATURE, HUMIDITY] })
+    ];
+    let api = Api::new("barn-pi");
+    api.set_sensors(&sensors, config);
+    api
+}
+
+fn api() -> Api
+{
+    api_with(&sensors_config(BTreeMap::new()))
+}
+
+fn get(api: &Api, path: &str) -> (u16, serde_json::Value)
+{
+    let (status, body) = api.respond("GET", path);
+    (status, serde_json::from_str(&body).unwrap())
+}
+
+
+#[test]
+fn sensors_are_listed_with_their_quantities()
+{
+    let (status, json) = get(&api(), "/sensors");
+    assert_eq!(status, 200);
+    assert_eq!(json["host"], "barn-pi");
+    assert_eq!(json["sensors"][1]["id"], "am2320-i2c-1");
+    assert_eq!(json["sensors"][1]["type"], "am2320");
+    assert_eq!(json["sensors"][1]["quantities"][1]["name"], "humidity");
+    assert_eq!(json["sensors"][1]["quantities"][1]["unit"], "%");
+}
+
+#[test]
+fn latest_reading_of_a_sensor()
+{
+    let api = api();
+    assert_eq!(get(&api, "/sensors/am2320-i2c-1").0, 503);
+
+    api.record(&payload());
+    let (status, json) = get(&api, "/sensors/am2320-i2c-1");
+    assert_eq!(status, 200);
+    assert_eq!(json["status"], "ok");
+    assert_eq!(json["quantities"][0]["value"], 21.5);
+    assert_eq!(json["timestamp"], "2021-04-20T12:00:00.000Z");
+
+    let (_, json) = get(&api, "/sensors/28-0316a2794eff");
+    assert_eq!(json["error"], "not_found");
+
+    assert_eq!(get(&api, "/sensors/nonexistent").0, 404);
+    assert_eq!(get(&api, "/nonexistent").0, 404);
+    assert_eq!(api.respond("POST", "/sensors").0, 405);
+}
+
+#[test]
+fn health_follows_reads_and_broker()
+{
+    let api = api();
+    let (status, json) = get(&api, "/health");
+    assert_eq!(status, 503);
+    assert_eq!(json["broker"], "not configured");
+
+    // The broker is known before the first reading
+    api.set_broker_connected(false);
+    let (status, json) = get(&api, "/health");
+    assert_eq!(status, 503);
+    assert_eq!(json["broker"], "disconnected");
+    assert_eq!(json["last_read"], serde_json::Value::Null);
+
+    api.record(&payload());
+    api.set_broker_connected(true);
+    let (status, json) = get(&api, "/health");
+    assert_eq!(status, 200);
+    assert_eq!(json["status"], "ok");
+    assert_eq!(json["broker"], "connected");
+    assert_eq!(json["last_read"]["timestamp_ms"], 1618920000000i64);
+
+    api.set_broker_connected(false);
+    let (status, json) = get(&api, "/health");
+    assert_eq!(status, 503);
+    assert_eq!(json["broker"], "disconnected");
+}
+
+#[test]
+fn aliased_sensors_carry_their_metadata()
+{
+    let metadata = SensorMetadata {
+        alias: Some("cellar".into()),
+        location: Some("cellar".into()),
+        description: None,
+        tags: vec!["wine".into()]
+    };
+    let mut devices = BTreeMap::new();
+    devices.insert("28-0316a2794eff".to_string(), DeviceConfig { metadata, ..DeviceConfig::default() });
+    let api = api_with(&sensors_config(devices));
+
+    let (_, json) = get(&api, "/sensors");
+    assert_eq!(json["sensors"][0]["alias"], "cellar");
+    assert_eq!(json["sensors"][0]["tags"], serde_json::json!(["wine"]));
+    assert!(json["sensors"][1].get("alias").is_none());
+
+    api.record(&aliased_payload(noon(), "cellar"));
+
+    for path in &["/sensors/cellar", "/sensors/28-0316a2794eff"] {
+        let (status, json) = get(&api, path);
+        assert_eq!(status, 200);
+        assert_eq!(json["id"], "28-0316a2794eff");
+        assert_eq!(json["quantities"][0]["value"], 12.5);
+    }
+}
@@ -0,0 +1,116 @@
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, TimeZone as _, Utc};
+use tempfile::TempDir;
+
+use sensor_reader::payload::{Payload, Timestamp, TimeZone};
+use sensor_reader::sensors::{SensorError, SensorReport, Reading, Quantity};
+
+
+/// A fake 1-Wire sysfs tree, laid out the same way as /sys/bus/w1/devices.
+pub struct FakeW1Bus
+{
+    pub dir: TempDir
+}
+
+impl FakeW1Bus
+{
+    pub fn new() -> FakeW1Bus
+    {
+        let dir = tempfile::tempdir().unwrap();
+        // The bus master is always present alongside the slaves.
+        fs::create_dir(dir.path().join("w1_bus_master1")).unwrap();
+        FakeW1Bus { dir }
+    }
+
+    pub fn path(&self) -> &Path
+    {
+        self.dir.path()
+    }
+
+    /// Add a device with the given w1_slave contents.
+    pub fn add_device(&self, id: &str, w1_slave: &str)
+    {
+        let device = self.dir.path().join(id);
+        fs::create_dir_all(&device).unwrap();
+        fs::write(device.join("w1_slave"), w1_slave).unwrap();
+    }
+
+    /// Add a device reporting the given scratchpad, formatted the way the
+    /// w1-therm driver does it.
+    pub fn add_scratchpad(&self, id: &str, scratchpad: &str, crc_ok: bool, millidegrees: i32)
+    {
+        let crc = scratchpad.rsplit(' ').next().unwrap();
+        let contents = format!(
+            "{pad} : crc={crc} {ok}\n{pad} t={t}\n",
+            pad = scratchpad,
+            crc = crc,
+            ok = if crc_ok { "YES" } else { "NO" },
+            t = millidegrees
+        );
+        self.add_device(id, &contents);
+    }
+
+    pub fn remove_device(&self, id: &str)
+    {
+        fs::remove_dir_all(self.dir.path().join(id)).unwrap();
+    }
+}
+
+/// Scratchpad for 23.125°C with a valid CRC.
+pub const SCRATCHPAD_23_125: &str = "72 01 4b 46 7f ff 0c 10 c6";
+
+/// Scratchpad for -10.125°C with a valid CRC.
+pub const SCRATCHPAD_MINUS_10_125: &str = "5e ff 4b 46 7f ff 0c 10 6a";
+
+/// Scratchpad holding the 85°C power-on-reset value, with a valid CRC.
+pub const SCRATCHPAD_POWER_ON_RESET: &str = "50 05 4b 46 7f ff 0c 10 1c";
+
+/// Scratchpad holding the 127.94°C bus error value, with a valid CRC.
+pub const SCRATCHPAD_BUS_ERROR: &str = "ff 07 4b 46 7f ff 01 10 2f";
+
+
+/// When the fixture cycles are read, 2021-04-20T12:00:00Z.
+pub fn noon() -> DateTime<Utc>
+{
+    Utc.ymd(2021, 4, 20).and_hms(12, 0, 0)
+}
+
+/// A cycle read at `time`, in which the AM2320 read `temperature` and 40%,
+/// and the DS18B20 `28-0316a2794eff` gave `ds18b20`.
+pub fn cycle(time: DateTime<Utc>, temperature: f32, ds18b20: Result<Reading, SensorError>) -> Payload
+{
+    let mut payload = Payload::new(Timestamp::new(time, TimeZone::Utc));
+    payload.insert(
+        "am2320-i2c-1",
+        SensorReport::new("am2320", Ok(Reading::new("am2320", vec![
+            Quantity::temperature(temperature),
+            Quantity::humidity(40.0)
+        ]))),
+        None
+    );
+    payload.insert("28-0316a2794eff", SensorReport::new("ds18b20", ds18b20), None);
+    payload
+}
+
+/// The AM2320 reading 21.5°C and 40% at noon, with the DS18B20 gone.
+pub fn payload() -> Payload
+{
+    cycle(noon(), 21.5, Err(SensorError::NotFound))
+}
+
+/// A cycle read at `time`, in which the DS18B20 `28-0316a2794eff` read
+/// 12.5°C under `alias`.
+pub fn aliased_payload(time: DateTime<Utc>, alias: &str) -> Payload
+{
+    let mut payload = Payload::new(Timestamp::new(time, TimeZone::Utc));
+    payload.insert_alias(
+        alias, "28-0316a2794eff",
+        SensorReport::new("ds18b20", Ok(Reading::new("ds18b20", vec![Quantity::temperature(12.5)]))),
+        None
+    );
+    payload
+}
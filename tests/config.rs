@@ -0,0 +1,465 @@
+
+use std::collections::HashMap;
+use std::fs;
+use std::time::Duration;
+
+use tempfile::{NamedTempFile, TempDir};
+
+use sensor_reader::config::{Config, ConfigError, InfluxApi};
+use sensor_reader::filelog::LogFormat;
+use sensor_reader::mqtt::{self, ProtocolVersion, Transport};
+use sensor_reader::payload::TimeZone;
+
+
+fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String>
+{
+    let vars: HashMap<String, String> = vars.iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    move |name: &str| vars.get(name).cloned()
+}
+
+fn minimal_config(ca_cert: &NamedTempFile) -> String
+{
+    format!(r#"
+host = "test-pi"
+
+[sinks.mqtt]
+host = "broker"
+user = "user"
+password = "password"
+topic = "sensors"
+ca_cert = "{}"
+"#, ca_cert.path().display())
+}
+
+fn problems(result: Result<Config, ConfigError>) -> Vec<String>
+{
+    match result {
+        Err(ConfigError::Invalid(problems)) => problems,
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("configuration should be invalid")
+    }
+}
+
+#[test]
+fn defaults_are_applied()
+{
+    let ca_cert = NamedTempFile::new().unwrap();
+    let config = Config::from_sources(&minimal_config(&ca_cert), &env(&[])).unwrap();
+
+    assert_eq!(config.host, "test-pi");
+    assert_eq!(config.read.interval, Duration::from_secs(10));
+    assert_eq!(config.read.deadline, Duration::from_secs(5));
+    assert_eq!(config.timestamps.zone, TimeZone::Utc);
+    let mqtt = config.sinks.mqtt.unwrap();
+    assert_eq!(mqtt.transport, Transport::Ssl);
+    assert_eq!(mqtt.port, 8883);
+    assert_eq!(mqtt.qos, 1);
+    assert_eq!(mqtt::server_uri(&mqtt), "ssl://broker:8883");
+}
+
+#[test]
+fn environment_overrides_file()
+{
+    let ca_cert = NamedTempFile::new().unwrap();
+    let config = Config::from_sources(
+        &minimal_config(&ca_cert),
+        &env(&[("MQTT_HOST", "other-broker"), ("MQTT_QOS", "2"), ("TIMESTAMP_TIMEZONE", "local")])
+    ).unwrap();
+
+    assert_eq!(config.timestamps.zone, TimeZone::Local);
+    let mqtt = config.sinks.mqtt.unwrap();
+    assert_eq!(mqtt.host, "other-broker");
+    assert_eq!(mqtt.qos, 2);
+}
+
+#[test]
+fn per_device_retry_overrides()
+{
+    let ca_cert = NamedTempFile::new().unwrap();
+    let contents = minimal_config(&ca_cert) + r#"
+[sensors.devices."28-0316a2794eff"]
+retries = 5
+"#;
+    let config = Config::from_sources(&contents, &env(&[])).unwrap();
+
+    assert_eq!(config.retry_policy("28-0316a2794eff").retries, 5);
+    assert_eq!(config.retry_policy("28-000005e2fdc3").retries, config.read.retry.retries);
+}
+
+#[test]
+fn all_problems_are_reported()
+{
+    let problems = problems(Config::from_sources(r#"
+host = "test-pi"
+
+[sinks.mqtt]
+qos = 3
+ca_cert = "/does/not/exist.pem"
+"#, &env(&[("MQTT_PORT", "not-a-port")])));
+
+    assert!(problems.iter().any(|p| p.starts_with("MQTT_PORT")));
+    assert!(problems.iter().any(|p| p.starts_with("sinks.mqtt.host")));
+    assert!(problems.iter().any(|p| p.starts_with("sinks.mqtt.qos")));
+    assert!(problems.iter().any(|p| p.contains("/does/not/exist.pem")));
+}
+
+#[test]
+fn unknown_settings_are_rejected()
+{
+    let result = Config::from_sources("[sinks.mqtt]\nhots = \"broker\"\n", &env(&[]));
+    assert!(matches!(result, Err(ConfigError::Parse(..))));
+}
+
+#[test]
+fn mqtt_is_optional_as_a_whole()
+{
+    let config = Config::from_sources("host = \"test-pi\"\n", &env(&[])).unwrap();
+    assert!(config.sinks.mqtt.is_none());
+
+    let problems = problems(Config::from_sources(
+        "host = \"test-pi\"\n", &env(&[("MQTT_HOST", "broker")])
+    ));
+    assert!(problems.iter().any(|p| p.starts_with("sinks.mqtt.topic")));
+}
+
+#[test]
+fn plain_tcp_needs_no_certificates()
+{
+    let config = Config::from_sources(r#"
+host = "test-pi"
+
+[sinks.mqtt]
+transport = "tcp"
+host = "bench"
+user = "user"
+password = "password"
+topic = "sensors"
+"#, &env(&[])).unwrap();
+
+    let mqtt = config.sinks.mqtt.unwrap();
+    assert_eq!(mqtt.ca_cert, None);
+    assert_eq!(mqtt::server_uri(&mqtt), "tcp://bench:1883");
+}
+
+#[test]
+fn websocket_transport_uses_path()
+{
+    let ca_cert = NamedTempFile::new().unwrap();
+    let config = Config::from_sources(
+        &minimal_config(&ca_cert),
+        &env(&[("MQTT_TRANSPORT", "wss"), ("MQTT_WS_PATH", "broker/ws")])
+    ).unwrap();
+
+    let mqtt = config.sinks.mqtt.unwrap();
+    assert_eq!(mqtt::server_uri(&mqtt), "wss://broker:443/broker/ws");
+}
+
+#[test]
+fn certificates_need_a_tls_transport()
+{
+    let ca_cert = NamedTempFile::new().unwrap();
+    let problems = problems(Config::from_sources(
+        &minimal_config(&ca_cert), &env(&[("MQTT_TRANSPORT", "ws")])
+    ));
+    assert!(problems.iter().any(|p| p.contains("does not use TLS")));
+}
+
+#[test]
+fn client_key_passphrase_is_optional()
+{
+    let ca_cert = NamedTempFile::new().unwrap();
+    let cert = NamedTempFile::new().unwrap();
+    let key = NamedTempFile::new().unwrap();
+    let config = Config::from_sources(&minimal_config(&ca_cert), &env(&[
+        ("CLIENT_CERT", cert.path().to_str().unwrap()),
+        ("CLIENT_CERT_KEY", key.path().to_str().unwrap())
+    ])).unwrap();
+
+    let client_cert = config.sinks.mqtt.unwrap().client_cert.unwrap();
+    assert_eq!(client_cert.cert, cert.path());
+    assert_eq!(client_cert.key, key.path());
+    assert_eq!(client_cert.key_pass, None);
+}
+
+#[test]
+fn partial_client_certificate_settings_are_rejected()
+{
+    let ca_cert = NamedTempFile::new().unwrap();
+    let cert = NamedTempFile::new().unwrap();
+
+    let missing_key = problems(Config::from_sources(&minimal_config(&ca_cert), &env(&[
+        ("CLIENT_CERT", cert.path().to_str().unwrap())
+    ])));
+    assert!(missing_key.iter().any(|p| p.contains("without sinks.mqtt.client_cert_key")));
+
+    let only_pass = problems(Config::from_sources(&minimal_config(&ca_cert), &env(&[
+        ("CLIENT_CERT_KEY_PASS", "secret")
+    ])));
+    assert!(only_pass.iter().any(|p| p.contains("client_cert_key_pass")));
+}
+
+#[test]
+fn credentials_are_optional_with_client_certificates()
+{
+    let cert = NamedTempFile::new().unwrap();
+    let key = NamedTempFile::new().unwrap();
+    let config = Config::from_sources(&format!(r#"
+host = "test-pi"
+
+[sinks.mqtt]
+host = "broker"
+topic = "sensors"
+client_cert = "{}"
+client_cert_key = "{}"
+"#, cert.path().display(), key.path().display()), &env(&[])).unwrap();
+
+    let mqtt = config.sinks.mqtt.unwrap();
+    assert_eq!(mqtt.user, None);
+    assert_eq!(mqtt.password, None);
+}
+
+#[test]
+fn password_without_user_is_rejected()
+{
+    let problems = problems(Config::from_sources(r#"
+host = "test-pi"
+
+[sinks.mqtt]
+transport = "tcp"
+host = "bench"
+password = "password"
+topic = "sensors"
+"#, &env(&[])));
+    assert!(problems.iter().any(|p| p.contains("without sinks.mqtt.user")));
+}
+
+#[test]
+fn secrets_are_read_from_files()
+{
+    let ca_cert = NamedTempFile::new().unwrap();
+    let secret = NamedTempFile::new().unwrap();
+    fs::write(secret.path(), "from-file\n").unwrap();
+
+    let contents = minimal_config(&ca_cert).replace("password = \"password\"\n", "");
+    let config = Config::from_sources(&contents, &env(&[
+        ("MQTT_PASSWORD_FILE", secret.path().to_str().unwrap())
+    ])).unwrap();
+    assert_eq!(config.sinks.mqtt.unwrap().password.as_deref(), Some("from-file"));
+
+    let both = problems(Config::from_sources(&minimal_config(&ca_cert), &env(&[
+        ("MQTT_PASSWORD_FILE", secret.path().to_str().unwrap())
+    ])));
+    assert!(both.iter().any(|p| p.starts_with("only one of sinks.mqtt.password")));
+
+    let missing = problems(Config::from_sources(&contents, &env(&[
+        ("MQTT_PASSWORD_FILE", "/nonexistent/password")
+    ])));
+    assert!(missing.iter().any(|p| p.contains("cannot read /nonexistent/password")));
+}
+
+#[test]
+fn secrets_are_picked_up_from_credentials_directory()
+{
+    let ca_cert = NamedTempFile::new().unwrap();
+    let credentials = TempDir::new().unwrap();
+    fs::write(credentials.path().join("mqtt_password"), "from-systemd").unwrap();
+
+    let contents = minimal_config(&ca_cert).replace("password = \"password\"\n", "");
+    let config = Config::from_sources(&contents, &env(&[
+        ("CREDENTIALS_DIRECTORY", credentials.path().to_str().unwrap())
+    ])).unwrap();
+    assert_eq!(config.sinks.mqtt.unwrap().password.as_deref(), Some("from-systemd"));
+
+    // An explicit password wins over the credential
+    let config = Config::from_sources(&minimal_config(&ca_cert), &env(&[
+        ("CREDENTIALS_DIRECTORY", credentials.path().to_str().unwrap())
+    ])).unwrap();
+    assert_eq!(config.sinks.mqtt.unwrap().password.as_deref(), Some("password"));
+}
+
+#[test]
+fn message_expiry_needs_mqtt_5()
+{
+    let ca_cert = NamedTempFile::new().unwrap();
+    let config = Config::from_sources(&minimal_config(&ca_cert), &env(&[
+        ("MQTT_VERSION", "5"), ("MQTT_MESSAGE_EXPIRY", "600")
+    ])).unwrap();
+    let mqtt = config.sinks.mqtt.unwrap();
+    assert_eq!(mqtt.version, ProtocolVersion::V5);
+    assert_eq!(mqtt.message_expiry, Some(Duration::from_secs(600)));
+
+    let problems = problems(Config::from_sources(&minimal_config(&ca_cert), &env(&[
+        ("MQTT_MESSAGE_EXPIRY", "600")
+    ])));
+    assert!(problems.iter().any(|p| p.contains("needs sinks.mqtt.version")));
+}
+
+#[test]
+fn metrics_listen_address()
+{
+    let config = Config::from_sources("host = \"test-pi\"\n", &env(&[])).unwrap();
+    assert!(config.metrics.is_none());
+
+    let config = Config::from_sources(
+        "host = \"test-pi\"\n", &env(&[("METRICS_LISTEN", "0.0.0.0:9100")])
+    ).unwrap();
+    assert_eq!(config.metrics.unwrap().listen, "0.0.0.0:9100".parse().unwrap());
+
+    let problems = problems(Config::from_sources(
+        "host = \"test-pi\"\n", &env(&[("METRICS_LISTEN", "9100")])
+    ));
+    assert!(problems.iter().any(|p| p.contains("METRICS_LISTEN")));
+}
+
+#[test]
+fn influxdb_api_follows_database_or_bucket()
+{
+    let config = Config::from_sources("host = \"test-pi\"\n", &env(&[
+        ("INFLUXDB_URL", "http://influx:8086"), ("INFLUXDB_DATABASE", "sensors")
+    ])).unwrap();
+    let influxdb = config.sinks.influxdb.unwrap();
+    assert_eq!(influxdb.api, InfluxApi::V1 { database: "sensors".into(), user: None, password: None });
+    assert_eq!(influxdb.measurement, "sensors");
+    assert!(config.sinks.mqtt.is_none());
+
+    let config = Config::from_sources("host = \"test-pi\"\n", &env(&[
+        ("INFLUXDB_URL", "http://influx:8086"), ("INFLUXDB_BUCKET", "sensors"),
+        ("INFLUXDB_ORG", "farm"), ("INFLUXDB_TOKEN", "s3cret")
+    ])).unwrap();
+    assert_eq!(config.sinks.influxdb.unwrap().api, InfluxApi::V2 {
+        org: "farm".into(), bucket: "sensors".into(), token: "s3cret".into()
+    });
+
+    let missing_token = problems(Config::from_sources("host = \"test-pi\"\n", &env(&[
+        ("INFLUXDB_URL", "http://influx:8086"), ("INFLUXDB_BUCKET", "sensors"), ("INFLUXDB_ORG", "farm")
+    ])));
+    assert!(missing_token.iter().any(|p| p.starts_with("sinks.influxdb.token")));
+
+    let neither = problems(Config::from_sources("host = \"test-pi\"\n", &env(&[
+        ("INFLUXDB_URL", "influx:8086")
+    ])));
+    assert!(neither.iter().any(|p| p.contains("http:// or https://")));
+    assert!(neither.iter().any(|p| p.contains("one of database")));
+}
+
+#[test]
+fn file_log_needs_a_directory()
+{
+    let config = Config::from_sources("host = \"test-pi\"\n", &env(&[
+        ("FILE_LOG_DIR", "/var/log/sensor_reader"), ("FILE_LOG_FORMAT", "csv")
+    ])).unwrap();
+    let file = config.sinks.file.unwrap();
+    assert_eq!(file.format, LogFormat::Csv);
+    assert!(file.rotate_daily);
+    assert!(file.compress);
+
+    let problems = problems(Config::from_sources("host = \"test-pi\"\n", &env(&[
+        ("FILE_LOG_FORMAT", "csv")
+    ])));
+    assert!(problems.iter().any(|p| p.starts_with("sinks.file.dir")));
+}
+
+#[test]
+fn rescanning_defaults_to_every_minute_and_can_be_turned_off()
+{
+    let ca_cert = NamedTempFile::new().unwrap();
+    let config = Config::from_sources(&minimal_config(&ca_cert), &env(&[])).unwrap();
+    assert_eq!(config.read.rescan_interval, Some(Duration::from_secs(60)));
+
+    let config = Config::from_sources(
+        &minimal_config(&ca_cert),
+        &env(&[("RESCAN_INTERVAL", "0")])
+    ).unwrap();
+    assert_eq!(config.read.rescan_interval, None);
+}
+
+#[test]
+fn durations_must_be_non_negative_and_not_absurdly_long()
+{
+    let ca_cert = NamedTempFile::new().unwrap();
+    let problems = problems(Config::from_sources(&minimal_config(&ca_cert), &env(&[
+        ("MQTT_READ_INTERVAL", "1e20"),
+        ("READ_RETRY_BACKOFF", "-1"),
+        ("READ_DEADLINE", "NaN")
+    ])));
+
+    assert!(problems.iter().any(|p| p.starts_with("read.interval must be at most")));
+    assert!(problems.iter().any(|p| p.starts_with("read.retry_backoff must be a non-negative")));
+    assert!(problems.iter().any(|p| p.starts_with("read.deadline must be a non-negative")));
+}
+
+#[test]
+fn sensor_aliases_and_metadata()
+{
+    let ca_cert = NamedTempFile::new().unwrap();
+    let contents = minimal_config(&ca_cert) + r#"
+[sensors.devices."28-0316a2794eff"]
+alias = "cellar"
+location = "Cellar, north wall"
+description = "Wine rack"
+tags = ["wine", "indoor"]
+"#;
+    let config = Config::from_sources(&contents, &env(&[])).unwrap();
+
+    let metadata = config.sensors.metadata("28-0316a2794eff");
+    assert_eq!(metadata.location.as_deref(), Some("Cellar, north wall"));
+    assert_eq!(metadata.tags, vec!["wine", "indoor"]);
+    assert_eq!(config.sensors.key("28-0316a2794eff"), "cellar");
+    assert_eq!(config.sensors.key("28-000005e2fdc3"), "28-000005e2fdc3");
+    assert_eq!(config.sensors.key("cellar"), "cellar");
+    assert_eq!(config.sensors.id("cellar"), "28-0316a2794eff");
+    assert_eq!(config.sensors.id("28-0316a2794eff"), "28-0316a2794eff");
+}
+
+#[test]
+fn aliases_must_be_unique_topic_levels()
+{
+    let ca_cert = NamedTempFile::new().unwrap();
+    let contents = minimal_config(&ca_cert) + r#"
+[sensors.devices."28-0316a2794eff"]
+alias = "cellar"
+
+[sensors.devices."28-000005e2fdc3"]
+alias = "cellar"
+
+[sensors.devices."28-0417c1a6b2ff"]
+alias = "barn/loft"
+"#;
+    let problems = problems(Config::from_sources(&contents, &env(&[])));
+
+    assert!(problems.iter().any(|p| p == "sensors.devices.28-0316a2794eff.alias is also the alias of 28-000005e2fdc3"));
+    assert!(problems.iter().any(|p| p.starts_with("sensors.devices.28-0417c1a6b2ff.alias must")));
+}
+
+#[test]
+fn aliases_must_not_take_other_topics()
+{
+    let ca_cert = NamedTempFile::new().unwrap();
+    let contents = minimal_config(&ca_cert) + r#"
+[sensors.devices."28-0316a2794eff"]
+alias = "28-000005e2fdc3"
+
+[sensors.devices."28-000005e2fdc3"]
+alias = "28-000005e2fdc3"
+
+[sensors.devices."28-0417c1a6b2ff"]
+alias = "status"
+
+[sensors.devices."am2320-i2c-1"]
+alias = "metadata"
+
+[sensors.devices."am2320-i2c-2"]
+alias = "28-unplugged"
+"#;
+    let problems = problems(Config::from_sources(&contents, &env(&[])));
+
+    assert!(problems.iter().any(|p| p == "sensors.devices.28-0316a2794eff.alias is the id of another sensor"));
+    // A sensor may have its own id as its alias
+    assert!(!problems.iter().any(|p| p == "sensors.devices.28-000005e2fdc3.alias is the id of another sensor"));
+    assert!(problems.iter().any(|p| p == "sensors.devices.28-0417c1a6b2ff.alias must not be one of status, events, metadata"));
+    assert!(problems.iter().any(|p| p == "sensors.devices.am2320-i2c-1.alias must not be one of status, events, metadata"));
+    // Not configured, but it could be plugged in
+    assert!(problems.iter().any(|p| p == "sensors.devices.am2320-i2c-2.alias must not start with 28- or am2320-, as sensor ids do"));
+}
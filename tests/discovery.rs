@@ -0,0 +1,114 @@
+
+mod common;
+
+use sensor_reader::discovery::Discovery;
+use sensor_reader::layout::TopicLayout;
+use sensor_reader::sensors::{AM2320Sensor, DS18B20Sensor, SensorMetadata};
+
+use common::*;
+
+
+#[test]
+fn single_quantity_sensor_uses_sensor_id()
+{
+    let bus = FakeW1Bus::new();
+    bus.add_scratchpad("28-0316a2794eff", SCRATCHPAD_23_125, true, 23125);
+    let sensor = DS18B20Sensor::new(bus.path(), "28-0316a2794eff");
+
+    let discovery = Discovery::new("homeassistant", "barn.pi", "farm/barn");
+    let messages = discovery.messages(&sensor, &SensorMetadata::default());
+
+    assert_eq!(messages.len(), 1);
+    let (topic, payload) = &messages[0];
+    assert_eq!(topic, "homeassistant/sensor/barn_pi/28-0316a2794eff/config");
+
+    let config: serde_json::Value = serde_json::from_str(payload).unwrap();
+    assert_eq!(config["state_topic"], "farm/barn");
+    assert_eq!(config["device_class"], "temperature");
+    assert_eq!(config["unit_of_measurement"], "°C");
+    assert_eq!(config["unique_id"], "barn_pi_28-0316a2794eff");
+    assert_eq!(
+        config["value_template"],
+        "{{ value_json.sensors['28-0316a2794eff'].quantities | default([]) \
+         | selectattr('name', 'eq', 'temperature') | map(attribute='value') | first | default(none) }}"
+    );
+}
+
+#[test]
+fn sensor_layout_reads_the_per_sensor_topic()
+{
+    let sensor = AM2320Sensor::new("/dev/i2c-1".into());
+    let metadata = SensorMetadata { alias: Some("loft".into()), ..SensorMetadata::default() };
+
+    let layout = TopicLayout { aggregate: false, per_sensor: true, per_quantity: false };
+    let discovery = Discovery::new("homeassistant", "barn", "farm/barn").with_layout(layout);
+    let (_, payload) = &discovery.messages(&sensor, &metadata)[1];
+
+    let config: serde_json::Value = serde_json::from_str(payload).unwrap();
+    assert_eq!(config["state_topic"], "farm/barn/loft");
+    assert_eq!(
+        config["value_template"],
+        "{{ value_json.quantities | default([]) \
+         | selectattr('name', 'eq', 'humidity') | map(attribute='value') | first | default(none) }}"
+    );
+}
+
+#[test]
+fn aliases_are_quoted_in_the_aggregate_template()
+{
+    let sensor = AM2320Sensor::new("/dev/i2c-1".into());
+    let metadata = SensorMetadata { alias: Some("Bob's shed".into()), ..SensorMetadata::default() };
+
+    let discovery = Discovery::new("homeassistant", "barn", "farm/barn");
+    let (_, payload) = &discovery.messages(&sensor, &metadata)[0];
+
+    let config: serde_json::Value = serde_json::from_str(payload).unwrap();
+    assert!(config["value_template"].as_str().unwrap()
+        .starts_with("{{ value_json.sensors['Bob\\'s shed'].quantities | default([])"));
+}
+
+#[test]
+fn multi_quantity_sensor_has_entity_per_quantity()
+{
+    let sensor = AM2320Sensor::new("/dev/i2c-1".into());
+
+    let discovery = Discovery::new("homeassistant", "barn", "farm/barn");
+    let topics: Vec<String> = discovery.messages(&sensor, &SensorMetadata::default()).into_iter()
+        .map(|(topic, _)| topic)
+        .collect();
+
+    assert_eq!(topics, vec![
+        "homeassistant/sensor/barn/am2320-i2c-1_temperature/config",
+        "homeassistant/sensor/barn/am2320-i2c-1_humidity/config"
+    ]);
+}
+
+#[test]
+fn entities_follow_node_availability()
+{
+    let sensor = AM2320Sensor::new("/dev/i2c-1".into());
+
+    let discovery = Discovery::new("homeassistant", "barn", "farm/barn");
+    for (_, payload) in discovery.messages(&sensor, &SensorMetadata::default()) {
+        let config: serde_json::Value = serde_json::from_str(&payload).unwrap();
+        assert_eq!(config["availability_topic"], "farm/barn/status");
+    }
+}
+
+#[test]
+fn aliased_sensor_keeps_its_id_but_follows_the_alias()
+{
+    let sensor = AM2320Sensor::new("/dev/i2c-1".into());
+    let metadata = SensorMetadata { alias: Some("loft".into()), ..SensorMetadata::default() };
+
+    let layout = TopicLayout { aggregate: false, per_sensor: false, per_quantity: true };
+    let discovery = Discovery::new("homeassistant", "barn", "farm/barn").with_layout(layout);
+    let (topic, payload) = &discovery.messages(&sensor, &metadata)[0];
+    assert_eq!(topic, "homeassistant/sensor/barn/am2320-i2c-1_temperature/config");
+
+    let config: serde_json::Value = serde_json::from_str(payload).unwrap();
+    assert_eq!(config["name"], "barn loft temperature");
+    assert_eq!(config["unique_id"], "barn_am2320-i2c-1_temperature");
+    assert_eq!(config["state_topic"], "farm/barn/loft/temperature");
+    assert_eq!(config["json_attributes_topic"], "farm/barn/loft/metadata");
+}
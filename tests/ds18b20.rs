@@ -0,0 +1,161 @@
+
+mod common;
+
+use sensor_reader::sensors::{get_sensors, Sensor, SensorError, SensorReport};
+use sensor_reader::sensors::ds18b20::{self, DS18B20Sensor, Scratchpad, ScratchpadError};
+
+use common::*;
+
+
+fn temperature(sensor: &dyn Sensor) -> f32
+{
+    sensor.read().unwrap().get("temperature").unwrap().value
+}
+
+#[test]
+fn discovers_only_ds18b20_devices()
+{
+    let bus = FakeW1Bus::new();
+    bus.add_scratchpad("28-0316a2794eff", SCRATCHPAD_23_125, true, 23125);
+    bus.add_scratchpad("28-000005e2fdc3", SCRATCHPAD_23_125, true, 23125);
+    // DS18S20, a different family code
+    bus.add_device("10-000802b4b2a1", "");
+
+    let sensors = ds18b20::discover(bus.path()).unwrap();
+    let ids: Vec<&str> = sensors.iter().map(|s| s.identifier()).collect();
+
+    assert_eq!(ids, vec!["28-000005e2fdc3", "28-0316a2794eff"]);
+}
+
+#[test]
+fn discovery_fails_for_missing_root()
+{
+    let bus = FakeW1Bus::new();
+    assert!(ds18b20::discover(&bus.path().join("missing")).is_err());
+}
+
+#[test]
+fn reads_positive_temperature()
+{
+    let bus = FakeW1Bus::new();
+    bus.add_scratchpad("28-0316a2794eff", SCRATCHPAD_23_125, true, 23125);
+
+    let sensor = DS18B20Sensor::new(bus.path(), "28-0316a2794eff");
+    assert_eq!(temperature(&sensor), 23.125);
+}
+
+#[test]
+fn reads_negative_temperature()
+{
+    let bus = FakeW1Bus::new();
+    bus.add_scratchpad("28-0316a2794eff", SCRATCHPAD_MINUS_10_125, true, -10125);
+
+    let sensor = DS18B20Sensor::new(bus.path(), "28-0316a2794eff");
+    assert_eq!(temperature(&sensor), -10.125);
+}
+
+#[test]
+fn driver_crc_failure_is_not_a_temperature()
+{
+    let bus = FakeW1Bus::new();
+    bus.add_scratchpad("28-0316a2794eff", SCRATCHPAD_23_125, false, 23125);
+
+    let sensor = DS18B20Sensor::new(bus.path(), "28-0316a2794eff");
+    assert!(matches!(sensor.read(), Err(SensorError::Checksum)));
+}
+
+#[test]
+fn removed_device_is_not_a_temperature()
+{
+    let bus = FakeW1Bus::new();
+    bus.add_scratchpad("28-0316a2794eff", SCRATCHPAD_23_125, true, 23125);
+
+    let sensor = DS18B20Sensor::new(bus.path(), "28-0316a2794eff");
+    bus.remove_device("28-0316a2794eff");
+
+    assert!(matches!(sensor.read(), Err(SensorError::NotFound)));
+}
+
+#[test]
+fn get_sensors_reads_end_to_end()
+{
+    let bus = FakeW1Bus::new();
+    bus.add_scratchpad("28-0316a2794eff", SCRATCHPAD_23_125, true, 23125);
+
+    let sensors = get_sensors(bus.path(), None).unwrap();
+    assert_eq!(sensors.len(), 1);
+
+    let reading = sensors[0].read().unwrap();
+    assert_eq!(reading.sensor_type, "ds18b20");
+    assert_eq!(reading.get("temperature").unwrap().unit, "°C");
+    assert_eq!(reading.get("temperature").unwrap().value, 23.125);
+}
+
+#[test]
+fn scratchpad_crc_is_checked_independently()
+{
+    let bus = FakeW1Bus::new();
+    // Temperature byte corrupted, but the driver still says YES
+    bus.add_scratchpad("28-0316a2794eff", "73 01 4b 46 7f ff 0c 10 c6", true, 23187);
+
+    let sensor = DS18B20Sensor::new(bus.path(), "28-0316a2794eff");
+    assert!(matches!(sensor.read(), Err(SensorError::Checksum)));
+}
+
+#[test]
+fn power_on_reset_value_is_rejected()
+{
+    let bus = FakeW1Bus::new();
+    bus.add_scratchpad("28-0316a2794eff", SCRATCHPAD_POWER_ON_RESET, true, 85000);
+
+    let sensor = DS18B20Sensor::new(bus.path(), "28-0316a2794eff");
+    assert!(matches!(sensor.read(), Err(SensorError::PowerOnReset)));
+}
+
+#[test]
+fn bus_error_value_is_rejected()
+{
+    let bus = FakeW1Bus::new();
+    bus.add_scratchpad("28-0316a2794eff", SCRATCHPAD_BUS_ERROR, true, 127937);
+
+    let sensor = DS18B20Sensor::new(bus.path(), "28-0316a2794eff");
+    assert!(matches!(sensor.read(), Err(SensorError::BusError)));
+}
+
+#[test]
+fn scratchpad_errors_are_distinct()
+{
+    let parse = |pad: &str| Scratchpad::parse(&format!("{} : crc=00 YES\n", pad))
+        .and_then(|p| p.temperature());
+
+    assert_eq!(parse(SCRATCHPAD_23_125), Ok(23.125));
+    assert_eq!(parse(SCRATCHPAD_POWER_ON_RESET), Err(ScratchpadError::PowerOnReset));
+    assert_eq!(parse(SCRATCHPAD_BUS_ERROR), Err(ScratchpadError::BusError));
+    assert_eq!(parse("00 00 00 00 00 00 00 00 00"), Err(ScratchpadError::BusError));
+    assert_eq!(
+        parse("72 01 4b 46 7f ff 0c 10 c7"),
+        Err(ScratchpadError::CrcMismatch { expected: 0xc7, computed: 0xc6 })
+    );
+    assert_eq!(parse("72 01 4b"), Err(ScratchpadError::Malformed));
+}
+
+#[test]
+fn report_names_the_error()
+{
+    let bus = FakeW1Bus::new();
+    bus.add_scratchpad("28-0316a2794eff", SCRATCHPAD_23_125, true, 23125);
+    bus.add_scratchpad("28-000005e2fdc3", SCRATCHPAD_POWER_ON_RESET, true, 85000);
+
+    let good = DS18B20Sensor::new(bus.path(), "28-0316a2794eff");
+    let json = serde_json::to_value(good.report()).unwrap();
+    assert_eq!(json["status"], "ok");
+    assert_eq!(json["type"], "ds18b20");
+    assert_eq!(json["quantities"][0]["value"], 23.125);
+
+    let bad = DS18B20Sensor::new(bus.path(), "28-000005e2fdc3");
+    assert!(matches!(bad.report(), SensorReport::Error { .. }));
+    let json = serde_json::to_value(bad.report()).unwrap();
+    assert_eq!(json["status"], "error");
+    assert_eq!(json["type"], "ds18b20");
+    assert_eq!(json["error"], "power_on_reset");
+}
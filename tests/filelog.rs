@@ -0,0 +1,150 @@
+
+mod common;
+
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::{TimeZone as _, Utc};
+use flate2::read::GzDecoder;
+use tempfile::TempDir;
+
+use sensor_reader::config::FileLogConfig;
+use sensor_reader::filelog::{csv_rows, FileLogger, LogFormat};
+use sensor_reader::payload::Payload;
+use sensor_reader::sensors::SensorError;
+
+use common::*;
+
+
+/// The fixture cycle, read at `hour` on the `day`th of April.
+fn at(day: u32, hour: u32) -> Payload
+{
+    cycle(Utc.ymd(2021, 4, day).and_hms(hour, 0, 0), 21.5, Err(SensorError::NotFound))
+}
+
+fn config(dir: &Path, format: LogFormat) -> FileLogConfig
+{
+    FileLogConfig {
+        dir: dir.to_path_buf(),
+        format,
+        max_file_bytes: 1024 * 1024,
+        rotate_daily: true,
+        compress: true,
+        max_age: Duration::from_secs(3600)
+    }
+}
+
+fn names(dir: &Path) -> Vec<String>
+{
+    let mut names: Vec<String> = fs::read_dir(dir).unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    names
+}
+
+fn gunzip(path: &Path) -> String
+{
+    let mut text = String::new();
+    GzDecoder::new(fs::File::open(path).unwrap()).read_to_string(&mut text).unwrap();
+    text
+}
+
+
+#[test]
+fn csv_has_a_row_per_quantity_and_failed_sensor()
+{
+    assert_eq!(csv_rows(&at(20, 12)), "\
+2021-04-20T12:00:00.000Z,1618920000000,28-0316a2794eff,,ds18b20,error,,,,not_found
+2021-04-20T12:00:00.000Z,1618920000000,am2320-i2c-1,,am2320,ok,temperature,21.5,°C,
+2021-04-20T12:00:00.000Z,1618920000000,am2320-i2c-1,,am2320,ok,humidity,40,%,
+");
+}
+
+#[test]
+fn csv_rows_of_aliased_sensors_keep_the_sensor_id()
+{
+    assert_eq!(csv_rows(&aliased_payload(noon(), "cellar")),
+        "2021-04-20T12:00:00.000Z,1618920000000,28-0316a2794eff,cellar,ds18b20,ok,temperature,12.5,°C,\n");
+}
+
+#[test]
+fn csv_files_start_with_a_header()
+{
+    let dir = TempDir::new().unwrap();
+    let config = config(dir.path(), LogFormat::Csv);
+    let mut logger = FileLogger::open(&config).unwrap();
+
+    logger.write(&at(20, 12)).unwrap();
+    logger.write(&at(20, 13)).unwrap();
+
+    let text = fs::read_to_string(dir.path().join("readings-2021-04-20-000.csv")).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 7);
+    assert!(lines[0].starts_with("timestamp,timestamp_ms,sensor_id"));
+}
+
+#[test]
+fn ndjson_appends_a_payload_per_line_and_rotates_daily()
+{
+    let dir = TempDir::new().unwrap();
+    let config = config(dir.path(), LogFormat::Ndjson);
+    let mut logger = FileLogger::open(&config).unwrap();
+
+    logger.write(&at(20, 22)).unwrap();
+    logger.write(&at(20, 23)).unwrap();
+    logger.write(&at(21, 0)).unwrap();
+
+    assert_eq!(names(dir.path()), vec![
+        "readings-2021-04-20-000.ndjson.gz",
+        "readings-2021-04-21-000.ndjson"
+    ]);
+
+    let finished = gunzip(&dir.path().join("readings-2021-04-20-000.ndjson.gz"));
+    assert_eq!(finished.lines().count(), 2);
+    for line in finished.lines() {
+        let json: serde_json::Value = serde_json::from_str(line).unwrap();
+        assert_eq!(json["sensors"]["am2320-i2c-1"]["status"], "ok");
+    }
+}
+
+#[test]
+fn files_rotate_by_size_and_resume_after_restart()
+{
+    let dir = TempDir::new().unwrap();
+    let mut config = config(dir.path(), LogFormat::Ndjson);
+    config.max_file_bytes = 1;
+    config.compress = false;
+
+    {
+        let mut logger = FileLogger::open(&config).unwrap();
+        logger.write(&at(20, 12)).unwrap();
+        logger.write(&at(20, 13)).unwrap();
+    }
+
+    let mut logger = FileLogger::open(&config).unwrap();
+    logger.write(&at(20, 14)).unwrap();
+
+    assert_eq!(names(dir.path()), vec![
+        "readings-2021-04-20-000.ndjson",
+        "readings-2021-04-20-001.ndjson",
+        "readings-2021-04-20-002.ndjson"
+    ]);
+}
+
+#[test]
+fn finished_files_past_retention_are_deleted()
+{
+    let dir = TempDir::new().unwrap();
+    let mut config = config(dir.path(), LogFormat::Ndjson);
+    config.max_age = Duration::from_secs(0);
+    let mut logger = FileLogger::open(&config).unwrap();
+
+    logger.write(&at(20, 12)).unwrap();
+    logger.write(&at(21, 12)).unwrap();
+    logger.write(&at(22, 12)).unwrap();
+
+    assert_eq!(names(dir.path()), vec!["readings-2021-04-22-000.ndjson"]);
+}
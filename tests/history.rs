@@ -0,0 +1,98 @@
+
+mod common;
+
+use std::time::Duration;
+
+use chrono::{TimeZone as _, Utc};
+use tempfile::TempDir;
+
+use sensor_reader::history::{parse_duration, parse_time, History};
+use sensor_reader::payload::{Payload, TimeZone};
+use sensor_reader::sensors::SensorError;
+
+use common::*;
+
+
+/// The fixture cycle, read at `minute` past noon with the AM2320 at
+/// `temperature`.
+fn at(minute: u32, temperature: f32) -> Payload
+{
+    cycle(Utc.ymd(2021, 4, 20).and_hms(12, minute, 0), temperature, Err(SensorError::NotFound))
+}
+
+#[test]
+fn readings_are_bucketed_with_min_max_and_mean()
+{
+    let dir = TempDir::new().unwrap();
+    let mut history = History::open(&dir.path().join("history.db")).unwrap();
+
+    assert_eq!(history.record(&at(0, 20.0)).unwrap(), 2);
+    history.record(&at(10, 22.0)).unwrap();
+    history.record(&at(20, 24.0)).unwrap();
+    history.record(&at(40, 30.0)).unwrap();
+    // Storing the same cycle again replaces it
+    history.record(&at(40, 30.0)).unwrap();
+
+    let from = Utc.ymd(2021, 4, 20).and_hms(12, 0, 0);
+    let to = Utc.ymd(2021, 4, 20).and_hms(13, 0, 0);
+    let buckets = history.query(
+        "am2320-i2c-1", Some("temperature"), from, to, Duration::from_secs(30 * 60)
+    ).unwrap();
+
+    assert_eq!(buckets.len(), 2);
+    assert_eq!(buckets[0].start_ms, from.timestamp_millis());
+    assert_eq!(buckets[0].count, 3);
+    assert_eq!(buckets[0].min, 20.0);
+    assert_eq!(buckets[0].max, 24.0);
+    assert_eq!(buckets[0].mean, 22.0);
+    assert_eq!(buckets[0].unit, "°C");
+    assert_eq!(buckets[1].start_ms, from.timestamp_millis() + 30 * 60 * 1000);
+    assert_eq!(buckets[1].count, 1);
+
+    let all = history.query("am2320-i2c-1", None, from, to, Duration::from_secs(3600)).unwrap();
+    let quantities: Vec<&str> = all.iter().map(|b| b.quantity.as_str()).collect();
+    assert_eq!(quantities, vec!["humidity", "temperature"]);
+
+    assert!(history.query("28-0316a2794eff", None, from, to, Duration::from_secs(3600)).unwrap().is_empty());
+}
+
+#[test]
+fn aliased_readings_are_stored_under_the_sensor_id()
+{
+    let dir = TempDir::new().unwrap();
+    let mut history = History::open(&dir.path().join("history.db")).unwrap();
+
+    // The alias changes between the two cycles
+    for (minute, alias) in &[(0, "cellar"), (10, "wine-cellar")] {
+        history.record(&aliased_payload(Utc.ymd(2021, 4, 20).and_hms(12, *minute, 0), alias)).unwrap();
+    }
+
+    let from = Utc.ymd(2021, 4, 20).and_hms(12, 0, 0);
+    let to = Utc.ymd(2021, 4, 20).and_hms(13, 0, 0);
+    let buckets = history.query("28-0316a2794eff", None, from, to, Duration::from_secs(3600)).unwrap();
+    assert_eq!(buckets.len(), 1);
+    assert_eq!(buckets[0].count, 2);
+    assert!(history.query("cellar", None, from, to, Duration::from_secs(3600)).unwrap().is_empty());
+}
+
+#[test]
+fn durations_and_times_parse()
+{
+    assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+    assert_eq!(parse_duration("15m").unwrap(), Duration::from_secs(15 * 60));
+    assert_eq!(parse_duration("2d").unwrap(), Duration::from_secs(2 * 24 * 60 * 60));
+    assert!(parse_duration("1w").is_err());
+    assert!(parse_duration("h").is_err());
+    assert!(parse_duration("18446744073709551615d").is_err());
+
+    let now = Utc.ymd(2021, 4, 20).and_hms(12, 0, 0);
+    assert_eq!(parse_time("24h", TimeZone::Utc, now).unwrap(), Utc.ymd(2021, 4, 19).and_hms(12, 0, 0));
+    assert_eq!(parse_time("2021-04-19", TimeZone::Utc, now).unwrap(), Utc.ymd(2021, 4, 19).and_hms(0, 0, 0));
+    assert_eq!(
+        parse_time("2021-04-19T06:00:00+02:00", TimeZone::Utc, now).unwrap(),
+        Utc.ymd(2021, 4, 19).and_hms(4, 0, 0)
+    );
+    assert!(parse_time("yesterday", TimeZone::Utc, now).is_err());
+    assert!(parse_time("9223372036854775807s", TimeZone::Utc, now).is_err());
+    assert!(parse_time("10000000000000s", TimeZone::Utc, now).is_err());
+}
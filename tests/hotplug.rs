@@ -0,0 +1,72 @@
+
+mod common;
+
+use chrono::{TimeZone as _, Utc};
+
+use sensor_reader::hotplug::{rescan, EventKind};
+use sensor_reader::payload::{Timestamp, TimeZone};
+use sensor_reader::sensors::get_sensors;
+
+use common::*;
+
+
+fn time() -> Timestamp
+{
+    Timestamp::new(Utc.ymd(2021, 4, 20).and_hms(12, 0, 0), TimeZone::Utc)
+}
+
+#[test]
+fn added_and_removed_probes_are_reported()
+{
+    let bus = FakeW1Bus::new();
+    bus.add_scratchpad("28-0316a2794eff", SCRATCHPAD_23_125, true, 23125);
+    bus.add_scratchpad("28-000005e2fdc3", SCRATCHPAD_23_125, true, 23125);
+    let mut sensors = get_sensors(bus.path(), None).unwrap();
+
+    bus.remove_device("28-000005e2fdc3");
+    bus.add_scratchpad("28-0417c1a6b2ff", SCRATCHPAD_23_125, true, 23125);
+    let events = rescan(&mut sensors, get_sensors(bus.path(), None).unwrap(), &time());
+
+    let summary: Vec<(EventKind, &str, &str)> = events.iter()
+        .map(|e| (e.event, e.id.as_str(), e.sensor_type))
+        .collect();
+    assert_eq!(summary, vec![
+        (EventKind::Removed, "28-000005e2fdc3", "ds18b20"),
+        (EventKind::Added, "28-0417c1a6b2ff", "ds18b20")
+    ]);
+
+    let ids: Vec<&str> = sensors.iter().map(|s| s.identifier()).collect();
+    assert_eq!(ids, vec!["28-0316a2794eff", "28-0417c1a6b2ff"]);
+}
+
+#[test]
+fn unchanged_bus_reports_nothing()
+{
+    let bus = FakeW1Bus::new();
+    bus.add_scratchpad("28-0316a2794eff", SCRATCHPAD_23_125, true, 23125);
+    let mut sensors = get_sensors(bus.path(), None).unwrap();
+
+    let events = rescan(&mut sensors, get_sensors(bus.path(), None).unwrap(), &time());
+
+    assert!(events.is_empty());
+    assert_eq!(sensors.len(), 1);
+}
+
+#[test]
+fn events_serialize_with_their_time()
+{
+    let bus = FakeW1Bus::new();
+    let mut sensors = get_sensors(bus.path(), None).unwrap();
+    bus.add_scratchpad("28-0316a2794eff", SCRATCHPAD_23_125, true, 23125);
+
+    let events = rescan(&mut sensors, get_sensors(bus.path(), None).unwrap(), &time());
+
+    let json: serde_json::Value = serde_json::to_value(&events[0]).unwrap();
+    assert_eq!(json, serde_json::json!({
+        "event": "added",
+        "id": "28-0316a2794eff",
+        "type": "ds18b20",
+        "timestamp": "2021-04-20T12:00:00.000Z",
+        "timestamp_ms": 1618920000000i64
+    }));
+}
@@ -0,0 +1,205 @@
+
+mod common;
+
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use sensor_reader::config::{InfluxApi, InfluxConfig};
+use sensor_reader::influxdb::{line_protocol, InfluxWriter};
+
+use common::*;
+
+
+/// A deadline that doesn't get in the way.
+fn later() -> Instant
+{
+    Instant::now() + Duration::from_secs(60)
+}
+
+const LINE: &str = "sensors,host=barn\\ pi,sensor_id=am2320-i2c-1,type=am2320 \
+    temperature=21.5,humidity=40 1618920000000000000";
+
+
+struct Request
+{
+    url: String,
+    authorization: Option<String>,
+    body: String
+}
+
+/// A stand-in InfluxDB server, answering each request with the next status
+/// in `statuses`. Returns its URL and the requests it received.
+fn stand_in(statuses: Vec<u16>) -> (String, Receiver<Request>)
+{
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", server.server_addr());
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        for status in statuses {
+            let mut request = server.recv().unwrap();
+            let mut body = String::new();
+            request.as_reader().read_to_string(&mut body).unwrap();
+            let authorization = request.headers().iter()
+                .find(|h| h.field.equiv("Authorization"))
+                .map(|h| h.value.to_string());
+            sender.send(Request { url: request.url().to_string(), authorization, body }).unwrap();
+            request.respond(tiny_http::Response::empty(status)).unwrap();
+        }
+    });
+
+    (url, receiver)
+}
+
+fn config(url: String, api: InfluxApi) -> InfluxConfig
+{
+    InfluxConfig {
+        url,
+        api,
+        measurement: "sensors".into(),
+        batch_size: 5000,
+        flush_interval: Duration::from_secs(0),
+        retries: 2,
+        retry_backoff: Duration::from_millis(10)
+    }
+}
+
+fn v2() -> InfluxApi
+{
+    InfluxApi::V2 { org: "farm".into(), bucket: "sensors".into(), token: "s3cret".into() }
+}
+
+
+#[test]
+fn readings_become_lines_with_nanosecond_timestamps()
+{
+    // The failed sensor has nothing to write
+    assert_eq!(line_protocol("sensors", "barn pi", &payload()), vec![LINE.to_string()]);
+}
+
+#[test]
+fn aliased_sensors_keep_their_id_as_sensor_id()
+{
+    assert_eq!(line_protocol("sensors", "barn", &aliased_payload(noon(), "cellar")), vec![
+        "sensors,alias=cellar,host=barn,sensor_id=28-0316a2794eff,type=ds18b20 \
+         temperature=12.5 1618920000000000000".to_string()
+    ]);
+}
+
+#[test]
+fn writes_to_v2_endpoint_with_token()
+{
+    let (url, requests) = stand_in(vec![204]);
+    let config = config(url, v2());
+    let mut writer = InfluxWriter::new(&config, "barn pi");
+
+    assert_eq!(writer.write(&payload(), later()), 0);
+
+    let request = requests.recv().unwrap();
+    assert!(request.url.starts_with("/api/v2/write?"));
+    assert!(request.url.contains("org=farm"));
+    assert!(request.url.contains("bucket=sensors"));
+    assert!(request.url.contains("precision=ns"));
+    assert_eq!(request.authorization.as_deref(), Some("Token s3cret"));
+    assert_eq!(request.body, LINE);
+}
+
+#[test]
+fn writes_to_v1_endpoint_with_credentials()
+{
+    let (url, requests) = stand_in(vec![204]);
+    let config = config(url, InfluxApi::V1 {
+        database: "sensors".into(),
+        user: Some("pi".into()),
+        password: Some("pass".into())
+    });
+    let mut writer = InfluxWriter::new(&config, "barn pi");
+
+    assert_eq!(writer.write(&payload(), later()), 0);
+
+    let request = requests.recv().unwrap();
+    assert!(request.url.starts_with("/write?"));
+    assert!(request.url.contains("db=sensors"));
+    assert!(request.url.contains("precision=n"));
+    assert!(!request.url.contains("u=pi"));
+    assert!(!request.url.contains("p=pass"));
+    // pi:pass
+    assert_eq!(request.authorization.as_deref(), Some("Basic cGk6cGFzcw=="));
+}
+
+#[test]
+fn server_errors_are_retried()
+{
+    let (url, requests) = stand_in(vec![503, 204]);
+    let config = config(url, v2());
+    let mut writer = InfluxWriter::new(&config, "barn pi");
+
+    assert_eq!(writer.write(&payload(), later()), 0);
+    assert_eq!(requests.recv().unwrap().body, LINE);
+    assert_eq!(requests.recv().unwrap().body, LINE);
+    assert_eq!(writer.buffered(), 0);
+}
+
+#[test]
+fn rejected_lines_are_dropped_without_retrying()
+{
+    let (url, requests) = stand_in(vec![400, 204]);
+    let config = config(url, v2());
+    let mut writer = InfluxWriter::new(&config, "barn pi");
+
+    assert_eq!(writer.write(&payload(), later()), 1);
+    assert_eq!(writer.buffered(), 0);
+
+    assert_eq!(writer.write(&payload(), later()), 0);
+    assert_eq!(requests.recv().unwrap().body, LINE);
+    assert_eq!(requests.recv().unwrap().body, LINE);
+}
+
+#[test]
+fn unwritten_lines_are_kept_for_the_next_flush()
+{
+    // The first write and both of its retries fail
+    let (url, requests) = stand_in(vec![500, 500, 500, 204]);
+    let config = config(url, v2());
+    let mut writer = InfluxWriter::new(&config, "barn pi");
+
+    assert_eq!(writer.write(&payload(), later()), 1);
+    assert_eq!(writer.buffered(), 1);
+
+    assert_eq!(writer.write(&payload(), later()), 0);
+    assert_eq!(writer.buffered(), 0);
+    let last = requests.iter().nth(3).unwrap();
+    assert_eq!(last.body, format!("{}\n{}", LINE, LINE));
+}
+
+#[test]
+fn lines_are_counted_once_while_the_server_is_down()
+{
+    // Both writes and all of their retries fail
+    let (url, _requests) = stand_in(vec![503, 503, 503, 503, 503, 503, 204]);
+    let config = config(url, v2());
+    let mut writer = InfluxWriter::new(&config, "barn pi");
+
+    assert_eq!(writer.write(&payload(), later()), 1);
+    assert_eq!(writer.write(&payload(), later()), 1);
+    assert_eq!(writer.buffered(), 2);
+
+    assert_eq!(writer.write(&payload(), later()), 0);
+    assert_eq!(writer.buffered(), 0);
+}
+
+#[test]
+fn retries_stop_at_the_deadline()
+{
+    let (url, requests) = stand_in(vec![503, 204]);
+    let mut config = config(url, v2());
+    config.retry_backoff = Duration::from_secs(30);
+    let mut writer = InfluxWriter::new(&config, "barn pi");
+
+    let start = Instant::now();
+    assert_eq!(writer.write(&payload(), start + Duration::from_secs(1)), 1);
+    assert!(start.elapsed() < Duration::from_secs(1));
+    assert_eq!(writer.buffered(), 1);
+    assert_eq!(requests.recv().unwrap().body, LINE);
+}
@@ -0,0 +1,73 @@
+
+mod common;
+
+use sensor_reader::layout::{self, TopicLayout};
+
+use common::*;
+
+
+fn topics(layout: &str) -> Vec<(String, String)>
+{
+    layout.parse::<TopicLayout>().unwrap().messages("farm/barn", &payload()).unwrap()
+        .into_iter()
+        .map(|m| (m.topic, m.body))
+        .collect()
+}
+
+#[test]
+fn aggregate_is_the_default()
+{
+    assert_eq!(TopicLayout::default(), "aggregate".parse().unwrap());
+    let messages = topics("aggregate");
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].0, "farm/barn");
+}
+
+#[test]
+fn per_sensor_topics_carry_json_with_timestamp()
+{
+    let messages = topics("sensor");
+    let names: Vec<&str> = messages.iter().map(|(t, _)| t.as_str()).collect();
+    assert_eq!(names, vec!["farm/barn/28-0316a2794eff", "farm/barn/am2320-i2c-1"]);
+
+    let json: serde_json::Value = serde_json::from_str(&messages[0].1).unwrap();
+    assert_eq!(json["status"], "error");
+    assert_eq!(json["error"], "not_found");
+    assert_eq!(json["timestamp"], "2021-04-20T12:00:00.000Z");
+}
+
+#[test]
+fn per_quantity_topics_are_plain_numbers()
+{
+    let messages = topics("quantity");
+    assert_eq!(messages, vec![
+        ("farm/barn/am2320-i2c-1/temperature".to_string(), "21.5".to_string()),
+        ("farm/barn/am2320-i2c-1/humidity".to_string(), "40".to_string())
+    ]);
+}
+
+#[test]
+fn layouts_can_be_combined()
+{
+    assert_eq!(topics("aggregate,sensor,quantity").len(), 5);
+    assert!("".parse::<TopicLayout>().is_err());
+    assert!("aggregate,everything".parse::<TopicLayout>().is_err());
+}
+
+#[test]
+fn messages_describe_their_contents()
+{
+    let layout: TopicLayout = "aggregate,quantity".parse().unwrap();
+    let messages = layout.messages("farm/barn", &payload()).unwrap();
+
+    assert_eq!(messages[0].content_type, layout::JSON);
+    assert_eq!(messages[1].content_type, layout::PLAIN_TEXT);
+    let properties: Vec<(&str, &str)> = messages[1].properties.iter()
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .collect();
+    assert_eq!(properties, vec![
+        ("firmware_version", env!("CARGO_PKG_VERSION")),
+        ("sensor_type", "am2320"),
+        ("unit", "°C")
+    ]);
+}
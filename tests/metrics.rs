@@ -0,0 +1,68 @@
+
+mod common;
+
+use sensor_reader::metrics::Metrics;
+use sensor_reader::sensors::{SensorError, Reading, Quantity};
+
+use common::*;
+
+
+#[test]
+fn quantities_are_gauges_labelled_by_sensor()
+{
+    let metrics = Metrics::new("barn-pi");
+    metrics.record(&cycle(noon(), 21.5, Ok(Reading::new("ds18b20", vec![Quantity::temperature(18.0)]))));
+    let text = metrics.render();
+
+    assert!(text.contains("# TYPE sensor_reader_temperature_celsius gauge\n"));
+    assert!(text.contains(
+        "sensor_reader_temperature_celsius{sensor=\"28-0316a2794eff\",type=\"ds18b20\",host=\"barn-pi\"} 18\n"
+    ));
+    assert!(text.contains(
+        "sensor_reader_humidity_percent{sensor=\"am2320-i2c-1\",type=\"am2320\",host=\"barn-pi\"} 40\n"
+    ));
+}
+
+#[test]
+fn failed_reads_are_counted_and_drop_the_gauge()
+{
+    let metrics = Metrics::new("barn-pi");
+    metrics.record(&cycle(noon(), 21.5, Ok(Reading::new("ds18b20", vec![Quantity::temperature(18.0)]))));
+    metrics.record(&cycle(noon(), 21.5, Err(SensorError::Checksum)));
+    metrics.record(&cycle(noon(), 21.5, Err(SensorError::Checksum)));
+    metrics.publish_failed("mqtt", 3);
+    metrics.publish_failed("mqtt", 0);
+    let text = metrics.render();
+
+    assert!(!text.contains("sensor=\"28-0316a2794eff\",type=\"ds18b20\",host=\"barn-pi\"}"));
+    assert!(text.contains(
+        "sensor_reader_read_errors_total{sensor=\"28-0316a2794eff\",type=\"ds18b20\",host=\"barn-pi\",error=\"checksum\"} 2\n"
+    ));
+    assert!(text.contains("sensor_reader_publish_failures_total{sink=\"mqtt\",host=\"barn-pi\"} 3\n"));
+}
+
+#[test]
+fn removed_sensors_lose_their_gauges()
+{
+    let metrics = Metrics::new("barn-pi");
+    metrics.record(&cycle(noon(), 21.5, Ok(Reading::new("ds18b20", vec![Quantity::temperature(18.0)]))));
+    metrics.remove_sensor("28-0316a2794eff");
+    let text = metrics.render();
+
+    assert!(!text.contains("sensor=\"28-0316a2794eff\""));
+    assert!(text.contains("sensor=\"am2320-i2c-1\""));
+}
+
+#[test]
+fn aliased_sensors_are_labelled_by_id_and_alias()
+{
+    let metrics = Metrics::new("barn-pi");
+    metrics.record(&aliased_payload(noon(), "cellar"));
+    assert!(metrics.render().contains(
+        "sensor_reader_temperature_celsius{sensor=\"28-0316a2794eff\",alias=\"cellar\",type=\"ds18b20\",host=\"barn-pi\"} 12.5\n"
+    ));
+
+    // Hot-plug events carry the id
+    metrics.remove_sensor("28-0316a2794eff");
+    assert!(!metrics.render().contains("cellar"));
+}
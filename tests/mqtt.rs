@@ -0,0 +1,64 @@
+
+use std::time::Duration;
+
+use sensor_reader::config::Config;
+use sensor_reader::mqtt::{self, expiry_left, Availability};
+
+
+#[test]
+fn expiry_counts_from_when_the_reading_was_taken()
+{
+    let hour = Duration::from_secs(3600);
+    assert_eq!(expiry_left(hour, 1_000_000, 1_000_000), Some(3600));
+    assert_eq!(expiry_left(hour, 1_000_000, 1_000_000 + 600_000), Some(3000));
+    // Never rounded down to zero, which would mean no expiry at all
+    assert_eq!(expiry_left(hour, 1_000_000, 1_000_000 + 3_599_001), Some(1));
+    assert_eq!(expiry_left(hour, 1_000_000, 1_000_000 + 3_600_000), None);
+}
+
+#[test]
+fn will_marks_the_node_offline()
+{
+    let config = Config::from_sources(r#"
+host = "barn"
+
+[sinks.mqtt]
+host = "broker"
+topic = "farm/barn"
+qos = 1
+"#, &|_| None).unwrap();
+    let will = mqtt::will(config.sinks.mqtt.as_ref().unwrap());
+
+    assert_eq!(will.topic(), "farm/barn/status");
+    assert_eq!(will.payload_str(), mqtt::OFFLINE);
+    assert_eq!(will.qos(), 1);
+    assert!(will.retained());
+}
+
+#[test]
+fn availability_is_announced_again_after_the_connection_is_lost()
+{
+    let mut availability = Availability::new();
+    let mut published = 0;
+
+    availability.announce_with(|| { published += 1; Ok(()) });
+    availability.announce_with(|| { published += 1; Ok(()) });
+    assert_eq!(published, 1);
+
+    // By now the broker will have published our will
+    availability.lost();
+    availability.announce_with(|| { published += 1; Ok(()) });
+    assert_eq!(published, 2);
+}
+
+#[test]
+fn failed_announcements_are_retried()
+{
+    let mut availability = Availability::new();
+    let mut attempts = 0;
+
+    availability.announce_with(|| { attempts += 1; Err(paho_mqtt::Error::from("not connected")) });
+    availability.announce_with(|| { attempts += 1; Ok(()) });
+    availability.announce_with(|| { attempts += 1; Ok(()) });
+    assert_eq!(attempts, 2);
+}
@@ -0,0 +1,69 @@
+
+use chrono::{TimeZone as _, Utc};
+
+use sensor_reader::payload::{Payload, Timestamp, TimeZone};
+use sensor_reader::sensors::{SensorError, SensorReport, Reading, Quantity};
+
+
+fn fixed_time() -> Timestamp
+{
+    Timestamp::new(Utc.ymd(2021, 4, 20).and_hms_milli(12, 30, 15, 250), TimeZone::Utc)
+}
+
+#[test]
+fn timestamp_is_rfc3339_and_epoch_ms()
+{
+    let time = fixed_time();
+    assert_eq!(time.timestamp, "2021-04-20T12:30:15.250Z");
+    assert_eq!(time.timestamp_ms, 1618921815250);
+}
+
+#[test]
+fn payload_carries_cycle_and_sensor_timestamps()
+{
+    let mut payload = Payload::new(fixed_time());
+    payload.insert(
+        "28-0316a2794eff",
+        SensorReport::new("ds18b20", Ok(Reading::new("ds18b20", vec![Quantity::temperature(21.5)]))),
+        Some(fixed_time())
+    );
+    payload.insert(
+        "28-000005e2fdc3",
+        SensorReport::new("ds18b20", Err(SensorError::Checksum)),
+        None
+    );
+
+    let json: serde_json::Value = serde_json::from_str(&payload.to_json().unwrap()).unwrap();
+
+    assert_eq!(json["timestamp"], "2021-04-20T12:30:15.250Z");
+    assert_eq!(json["timestamp_ms"], 1618921815250i64);
+
+    let good = &json["sensors"]["28-0316a2794eff"];
+    assert_eq!(good["status"], "ok");
+    assert_eq!(good["timestamp_ms"], 1618921815250i64);
+
+    let bad = &json["sensors"]["28-000005e2fdc3"];
+    assert_eq!(bad["status"], "error");
+    assert_eq!(bad["error"], "checksum");
+    assert!(bad.get("timestamp").is_none());
+}
+
+#[test]
+fn aliased_sensors_are_keyed_by_alias_and_keep_their_id()
+{
+    let mut payload = Payload::new(fixed_time());
+    payload.insert_alias(
+        "cellar",
+        "28-0316a2794eff",
+        SensorReport::new("ds18b20", Ok(Reading::new("ds18b20", vec![Quantity::temperature(12.5)]))),
+        None
+    );
+
+    let json: serde_json::Value = serde_json::from_str(&payload.to_json().unwrap()).unwrap();
+    assert_eq!(json["sensors"]["cellar"]["id"], "28-0316a2794eff");
+
+    let entry = &payload.sensors["cellar"];
+    let message = serde_json::to_value(payload.sensor_message(entry)).unwrap();
+    assert_eq!(message["id"], "28-0316a2794eff");
+    assert_eq!(message["timestamp_ms"], 1618921815250i64);
+}
@@ -0,0 +1,114 @@
+
+use std::fs;
+use std::time::Duration;
+
+use chrono::Utc;
+
+use sensor_reader::queue::{DiskQueue, QueuedMessage};
+
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+fn message(n: i64, timestamp_ms: i64) -> QueuedMessage
+{
+    QueuedMessage {
+        topic: "farm/barn".into(),
+        payload: format!("{{\"n\":{}}}", n),
+        timestamp_ms,
+        content_type: Some("application/json".into()),
+        properties: vec![("firmware_version".into(), "1.0.0".into())]
+    }
+}
+
+fn drain(queue: &mut DiskQueue) -> Vec<QueuedMessage>
+{
+    let mut sent = Vec::new();
+    queue.replay(|m| -> Result<(), ()> {
+        sent.push(m.clone());
+        Ok(())
+    }).unwrap();
+    sent
+}
+
+#[test]
+fn replays_in_order_across_restarts()
+{
+    let dir = tempfile::tempdir().unwrap();
+    let now = Utc::now().timestamp_millis();
+
+    {
+        let mut queue = DiskQueue::open(dir.path(), 1 << 20, DAY).unwrap();
+        for n in 0..3 {
+            queue.push(&message(n, now)).unwrap();
+        }
+    }
+
+    let mut queue = DiskQueue::open(dir.path(), 1 << 20, DAY).unwrap();
+    queue.push(&message(3, now)).unwrap();
+
+    let sent: Vec<i64> = drain(&mut queue).iter().map(|m| m.payload[5..6].parse().unwrap()).collect();
+    assert_eq!(sent, vec![0, 1, 2, 3]);
+    assert!(queue.is_empty().unwrap());
+}
+
+#[test]
+fn partly_written_messages_are_cleaned_up()
+{
+    let dir = tempfile::tempdir().unwrap();
+    let now = Utc::now().timestamp_millis();
+    fs::write(dir.path().join(format!("{:015}-{:010}.tmp", now, 0)), "{\"topic\":").unwrap();
+
+    let mut queue = DiskQueue::open(dir.path(), 1 << 20, DAY).unwrap();
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    assert!(drain(&mut queue).is_empty());
+}
+
+#[test]
+fn replay_stops_at_first_failure()
+{
+    let dir = tempfile::tempdir().unwrap();
+    let now = Utc::now().timestamp_millis();
+    let mut queue = DiskQueue::open(dir.path(), 1 << 20, DAY).unwrap();
+    for n in 0..3 {
+        queue.push(&message(n, now)).unwrap();
+    }
+
+    let mut attempts = 0;
+    let sent = queue.replay(|_| {
+        attempts += 1;
+        if attempts == 2 { Err(()) } else { Ok(()) }
+    }).unwrap();
+
+    assert_eq!(sent, 1);
+    assert_eq!(queue.len().unwrap(), 2);
+    assert_eq!(drain(&mut queue)[0], message(1, now));
+}
+
+#[test]
+fn oldest_messages_are_dropped_when_full()
+{
+    let dir = tempfile::tempdir().unwrap();
+    let now = Utc::now().timestamp_millis();
+    let size = serde_json::to_vec(&message(0, now)).unwrap().len() as u64;
+
+    let mut queue = DiskQueue::open(dir.path(), 2 * size, DAY).unwrap();
+    for n in 0..5 {
+        queue.push(&message(n, now)).unwrap();
+    }
+
+    assert_eq!(drain(&mut queue), vec![message(3, now), message(4, now)]);
+}
+
+#[test]
+fn expired_messages_are_dropped()
+{
+    let dir = tempfile::tempdir().unwrap();
+    let now = Utc::now().timestamp_millis();
+    let two_days_ago = now - 2 * DAY.as_millis() as i64;
+
+    let mut queue = DiskQueue::open(dir.path(), 1 << 20, DAY).unwrap();
+    queue.push(&message(0, two_days_ago)).unwrap();
+    queue.push(&message(1, now)).unwrap();
+
+    assert_eq!(drain(&mut queue), vec![message(1, now)]);
+}
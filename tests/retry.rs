@@ -0,0 +1,98 @@
+
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+use sensor_reader::sensors::{Sensor, SensorError, Reading, Quantity, QuantityKind, RetryPolicy, TEMPERATURE};
+
+
+/// A sensor that fails a fixed number of times before it succeeds.
+struct FlakySensor
+{
+    failures: u32,
+    calls: Cell<u32>,
+    error: fn() -> SensorError
+}
+
+impl FlakySensor
+{
+    fn new(failures: u32, error: fn() -> SensorError) -> FlakySensor
+    {
+        FlakySensor { failures, calls: Cell::new(0), error }
+    }
+}
+
+impl Sensor for FlakySensor
+{
+    fn identifier(&self) -> &str
+    {
+        "flaky"
+    }
+
+    fn sensor_type(&self) -> &'static str
+    {
+        "test"
+    }
+
+    fn quantities(&self) -> &'static [QuantityKind]
+    {
+        &[TEMPERATURE]
+    }
+
+    fn read(&self) -> Result<Reading, SensorError>
+    {
+        let calls = self.calls.get();
+        self.calls.set(calls + 1);
+        if calls < self.failures {
+            Err((self.error)())
+        } else {
+            Ok(Reading::new("test", vec![Quantity::temperature(20.0)]))
+        }
+    }
+}
+
+fn far_future() -> Instant
+{
+    Instant::now() + Duration::from_secs(60)
+}
+
+#[test]
+fn retries_until_success()
+{
+    let sensor = FlakySensor::new(2, || SensorError::Checksum);
+    let policy = RetryPolicy::new(2, Duration::from_millis(1));
+
+    assert!(policy.read(&sensor, far_future()).is_ok());
+    assert_eq!(sensor.calls.get(), 3);
+}
+
+#[test]
+fn gives_up_after_retries()
+{
+    let sensor = FlakySensor::new(5, || SensorError::Checksum);
+    let policy = RetryPolicy::new(2, Duration::from_millis(1));
+
+    assert!(matches!(policy.read(&sensor, far_future()), Err(SensorError::Checksum)));
+    assert_eq!(sensor.calls.get(), 3);
+}
+
+#[test]
+fn missing_device_is_not_retried()
+{
+    let sensor = FlakySensor::new(1, || SensorError::NotFound);
+    let policy = RetryPolicy::new(2, Duration::from_millis(1));
+
+    assert!(matches!(policy.read(&sensor, far_future()), Err(SensorError::NotFound)));
+    assert_eq!(sensor.calls.get(), 1);
+}
+
+#[test]
+fn retries_stop_at_deadline()
+{
+    let sensor = FlakySensor::new(5, || SensorError::Checksum);
+    let policy = RetryPolicy::new(5, Duration::from_secs(10));
+
+    let start = Instant::now();
+    assert!(policy.read(&sensor, start + Duration::from_secs(1)).is_err());
+    assert_eq!(sensor.calls.get(), 1);
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
@@ -0,0 +1,23 @@
+
+use std::time::{Duration, Instant};
+
+use signal_hook::consts::SIGTERM;
+use signal_hook::low_level::raise;
+
+use sensor_reader::shutdown::Shutdown;
+
+
+#[test]
+fn sigterm_interrupts_sleep()
+{
+    let shutdown = Shutdown::register().unwrap();
+    assert!(shutdown.sleep(Duration::from_millis(10)));
+    assert!(!shutdown.requested());
+
+    raise(SIGTERM).unwrap();
+
+    let start = Instant::now();
+    assert!(!shutdown.sleep(Duration::from_secs(60)));
+    assert!(start.elapsed() < Duration::from_secs(1));
+    assert!(shutdown.requested());
+}